@@ -18,10 +18,12 @@
 mod bfs;
 mod ford_fulkerson;
 mod minimum_cost_flow;
+mod successive_shortest_path;
 
 use bfs::BFS;
 pub use ford_fulkerson::FordFulkerson;
 pub use minimum_cost_flow::MinimumCostFlow;
+pub use successive_shortest_path::SuccessiveShortestPath;
 use std::fmt::Debug;
 
 pub trait Graphable {
@@ -183,6 +185,16 @@ impl<T: Debug> Graph<T> {
         index
     }
 
+    #[must_use]
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    #[must_use]
+    pub fn edge_count(&self) -> usize {
+        self.edges.len()
+    }
+
     pub fn add_edge(
         &mut self,
         source: NodeIndex,
@@ -0,0 +1,91 @@
+use super::*;
+
+pub trait SuccessiveShortestPath {
+    /// Computes minimum cost maximum flow by augmenting along cheapest paths from source to
+    /// sink, requires graph without negative cost cycles. Residual graph is rebuilt and searched
+    /// by Bellman-Ford for every augmenting path.
+    fn successive_shortest_path(&mut self);
+}
+
+impl<T: Clone + Debug> SuccessiveShortestPath for Graph<T> {
+    fn successive_shortest_path(&mut self) {
+        loop {
+            let (residual, res_index_to_g_index) = self.residual_graph();
+            let path = match residual.shortest_path() {
+                Some(path) => path,
+                None => break,
+            };
+            let capacity = path.iter().map(|x| residual.edges[x.0].capacity).min().unwrap();
+            for edge in path {
+                match res_index_to_g_index[edge.0] {
+                    Ok(i) => self.edges[i.0].flow += Flow(capacity.0),
+                    Err(i) => self.edges[i.0].flow -= Flow(capacity.0),
+                }
+            }
+        }
+    }
+}
+
+impl<T: Debug> Graph<T> {
+    /// Cheapest path from source to sink over edges with capacity, Bellman-Ford since residual
+    /// edges have negative costs
+    fn shortest_path(&self) -> Option<Vec<EdgeIndex>> {
+        let mut distance = vec![Cost::MAX; self.nodes.len()];
+        let mut parent: Vec<Option<EdgeIndex>> = vec![None; self.nodes.len()];
+        distance[self.source.0] = Cost(0);
+
+        for _ in 0..self.nodes.len() - 1 {
+            let mut changed = false;
+            for edge in &self.edges {
+                let (u, v) = (edge.source.0, edge.target.0);
+                if distance[u] != Cost::MAX
+                    && edge.capacity != Capacity(0)
+                    && distance[u] + edge.cost < distance[v]
+                {
+                    distance[v] = distance[u] + edge.cost;
+                    parent[v] = Some(edge.index);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut path = vec![];
+        let mut node = self.sink;
+        while node != self.source {
+            let edge = parent[node.0]?;
+            path.push(edge);
+            node = self.edges[edge.0].source;
+        }
+        path.reverse();
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn same_as_cycle_cancelling() {
+        let build = || {
+            let mut graph = Graph::new();
+            let a = graph.add_node(2);
+            let b = graph.add_node(3);
+            graph.add_edge(graph.source, a, Capacity(2), Cost(1));
+            graph.add_edge(graph.source, b, Capacity(4), Cost(1));
+            graph.add_edge(a, b, Capacity(3), Cost(1));
+            graph.add_edge(a, graph.sink, Capacity(1), Cost(4));
+            graph.add_edge(b, graph.sink, Capacity(6), Cost(1));
+            graph
+        };
+        let mut expected = build();
+        expected.minimum_cost_flow();
+        let mut graph = build();
+        graph.successive_shortest_path();
+        assert_eq!(expected.graphviz(), graph.graphviz());
+    }
+}
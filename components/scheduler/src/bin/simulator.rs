@@ -0,0 +1,23 @@
+//! Replays a workload trace against virtual servers and prints the report as JSON
+//!
+//! Usage: `simulator <trace.json> [profile interval seconds] [horizon seconds]`
+#![deny(warnings)]
+
+use scheduler::simulator::{Simulator, Trace};
+use std::error::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().ok_or("usage: simulator <trace.json> [interval] [horizon]")?;
+    let interval = args.next().map(|x| x.parse()).transpose()?.unwrap_or(30);
+    let horizon = args.next().map(|x| x.parse()).transpose()?.unwrap_or(7 * 24 * 3600);
+
+    let trace: Trace = serde_json::from_slice(&std::fs::read(&path)?)?;
+    let data_dir =
+        std::env::temp_dir().join(format!("scheduler-simulation-{}", uuid::Uuid::new_v4()));
+    let report = Simulator::new(trace, &data_dir, interval, horizon).run().await;
+    let _ = std::fs::remove_dir_all(&data_dir);
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
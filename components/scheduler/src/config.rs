@@ -0,0 +1,215 @@
+//! Options of the scheduler binary. Defaults are overridden by the TOML file, environment
+//! variables `SCHEDULER_<OPTION>` and command line flags, in this order.
+
+use crate::prelude::*;
+use crate::scheduler::{Solver, WeightConfig};
+use clap::{App, Arg};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use telemetry::TraceExporter;
+
+/// Name, flag and help of options accepted on command line and in environment
+const OPTIONS: [(&str, &str, &str); 16] = [
+    ("rpc_address", "rpc-address", "Address gRPC server listens on"),
+    ("web_address", "web-address", "Address web UI listens on"),
+    ("advertise_address", "advertise-address", "Address agents reach this replica at"),
+    ("tls_cert", "tls-cert", "PEM certificate of gRPC and web servers"),
+    ("tls_key", "tls-key", "PEM private key of the certificate"),
+    ("tls_ca", "tls-ca", "PEM certificate authority agents are verified against"),
+    ("data_dir", "data-dir", "Directory with persisted state"),
+    ("core_threads", "core-threads", "Number of runtime worker threads"),
+    ("log_level", "log-level", "Log level of dependencies"),
+    ("scheduler_log_level", "scheduler-log-level", "Log level of the scheduler"),
+    ("solver", "solver", "Flow graph solver: cycle_cancelling or successive_shortest_path"),
+    ("schedule_interval", "schedule-interval", "Seconds between periodic scheduling rounds"),
+    ("lease_file", "lease-file", "Lease file shared by replicas"),
+    ("replica_id", "replica-id", "Identity of the replica in leader election"),
+    ("trace_exporter", "trace-exporter", "Exporter of trace spans: none, stdout or otlp"),
+    ("otlp_endpoint", "otlp-endpoint", "Address of OpenTelemetry collector"),
+];
+
+/// Certificates for gRPC and web servers, agents have to present certificate signed by `ca`
+/// when set
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub ca: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub rpc_address: SocketAddr,
+    pub web_address: SocketAddr,
+    /// Address agents reach this replica at, announced when it is leader
+    pub advertise_address: String,
+    pub tls: Option<TlsConfig>,
+    pub data_dir: PathBuf,
+    pub core_threads: usize,
+    pub log_level: String,
+    pub scheduler_log_level: String,
+    pub solver: Solver,
+    /// Seconds between scheduling rounds run besides the ones triggered by cluster changes
+    pub schedule_interval: Option<u64>,
+    /// Resource weights replacing the persisted ones
+    pub weights: Option<WeightConfig>,
+    pub lease_file: Option<PathBuf>,
+    /// Random identity is generated when unset
+    pub replica_id: Option<String>,
+    pub trace_exporter: TraceExporter,
+    /// Collector spans are sent to by `otlp` exporter
+    pub otlp_endpoint: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rpc_address: "[::]:50051".parse().unwrap(),
+            web_address: "0.0.0.0:8080".parse().unwrap(),
+            advertise_address: "http://[::1]:50051".to_string(),
+            tls: None,
+            data_dir: PathBuf::from("./data"),
+            core_threads: 4,
+            log_level: "info".to_string(),
+            scheduler_log_level: "trace".to_string(),
+            solver: Solver::default(),
+            schedule_interval: None,
+            weights: None,
+            lease_file: None,
+            replica_id: None,
+            trace_exporter: TraceExporter::default(),
+            otlp_endpoint: "localhost:55680".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads configuration of the process from file given by `--config` or `SCHEDULER_CONFIG`,
+    /// environment and command line
+    pub fn from_args() -> BoxResult<Self> {
+        let matches = Self::app().get_matches();
+        let path = matches
+            .value_of("config")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("SCHEDULER_CONFIG").map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => Self::load(&path)?,
+            None => Self::default(),
+        };
+        config.apply(|x| std::env::var(format!("SCHEDULER_{}", x.to_uppercase())).ok())?;
+        config.apply(|x| matches.value_of(x).map(String::from))?;
+        Ok(config)
+    }
+
+    fn app() -> App<'static, 'static> {
+        let config = Arg::with_name("config").short("c").long("config").takes_value(true);
+        let app = App::new("scheduler").arg(config.help("TOML configuration file"));
+        OPTIONS.iter().fold(app, |app, (name, flag, help)| {
+            app.arg(Arg::with_name(name).long(flag).takes_value(true).help(help))
+        })
+    }
+
+    pub fn load(path: &PathBuf) -> BoxResult<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Can't read config '{}': {}", path.display(), e))?;
+        let config = toml::from_str(&content)
+            .map_err(|e| format!("Invalid config '{}': {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// Overrides options with values `lookup` finds by option name
+    fn apply(&mut self, lookup: impl Fn(&str) -> Option<String>) -> BoxResult<()> {
+        let invalid = |name: &str, e: &dyn std::fmt::Display| format!("Invalid {}: {}", name, e);
+        if let Some(x) = lookup("rpc_address") {
+            self.rpc_address = x.parse().map_err(|e| invalid("rpc_address", &e))?;
+        }
+        if let Some(x) = lookup("web_address") {
+            self.web_address = x.parse().map_err(|e| invalid("web_address", &e))?;
+        }
+        if let Some(x) = lookup("advertise_address") {
+            self.advertise_address = x;
+        }
+        if let Some(x) = lookup("tls_cert") {
+            self.tls.get_or_insert_with(Default::default).cert = PathBuf::from(x);
+        }
+        if let Some(x) = lookup("tls_key") {
+            self.tls.get_or_insert_with(Default::default).key = PathBuf::from(x);
+        }
+        if let Some(x) = lookup("tls_ca") {
+            self.tls.get_or_insert_with(Default::default).ca = Some(PathBuf::from(x));
+        }
+        if let Some(x) = lookup("data_dir") {
+            self.data_dir = PathBuf::from(x);
+        }
+        if let Some(x) = lookup("core_threads") {
+            self.core_threads = x.parse().map_err(|e| invalid("core_threads", &e))?;
+        }
+        if let Some(x) = lookup("log_level") {
+            self.log_level = x;
+        }
+        if let Some(x) = lookup("scheduler_log_level") {
+            self.scheduler_log_level = x;
+        }
+        if let Some(x) = lookup("solver") {
+            self.solver = x.parse()?;
+        }
+        if let Some(x) = lookup("schedule_interval") {
+            self.schedule_interval = Some(x.parse().map_err(|e| invalid("schedule_interval", &e))?);
+        }
+        if let Some(x) = lookup("lease_file") {
+            self.lease_file = Some(PathBuf::from(x));
+        }
+        if let Some(x) = lookup("replica_id") {
+            self.replica_id = Some(x);
+        }
+        if let Some(x) = lookup("trace_exporter") {
+            self.trace_exporter = x.parse()?;
+        }
+        if let Some(x) = lookup("otlp_endpoint") {
+            self.otlp_endpoint = x;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            web_address = "127.0.0.1:9090"
+            solver = "successive_shortest_path"
+            schedule_interval = 30
+
+            [tls]
+            cert = "scheduler.pem"
+            key = "scheduler.key"
+
+            [weights.cluster]
+            ipc = 3
+            memory = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rpc_address, Config::default().rpc_address);
+        assert_eq!(config.solver, Solver::SuccessiveShortestPath);
+        assert_eq!(config.weights.as_ref().unwrap().cluster.get("ipc"), Decimal::new(3, 0));
+
+        let flags: HashMap<&str, &str> =
+            vec![("web_address", "[::]:80"), ("tls_ca", "ca.pem")].into_iter().collect();
+        config.apply(|x| flags.get(x).map(|x| x.to_string())).unwrap();
+        assert_eq!(config.web_address, "[::]:80".parse().unwrap());
+        assert_eq!(config.schedule_interval, Some(30));
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.cert, PathBuf::from("scheduler.pem"));
+        assert_eq!(tls.ca, Some(PathBuf::from("ca.pem")));
+
+        let solver = |x: &str| if x == "solver" { Some("simplex".to_string()) } else { None };
+        assert!(config.apply(solver).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Active/passive replicas. Replicas campaign for a lease on a shared store, the holder is the
+//! leader scheduling the cluster. Learned state is persisted in the data directory, replicas
+//! sharing it restore the state when they become leader and step down when they lose the lease.
+
+use crate::prelude::*;
+use crate::scheduler::Scheduler;
+use chrono::{DateTime, Utc};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::fs::{self, OpenOptions};
+
+/// Leadership held by a replica until it expires
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Lease {
+    /// Identity of the replica
+    pub holder: String,
+    /// Address agents connect to
+    pub address: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Lease {
+    /// Lease after `candidate` campaigned at `now`, candidate takes over lease that is missing,
+    /// expired or already its own
+    fn resolve(current: Option<Lease>, candidate: &Lease, now: DateTime<Utc>) -> Lease {
+        match current {
+            Some(lease) if lease.holder != candidate.holder && lease.expires_at > now => lease,
+            _ => candidate.clone(),
+        }
+    }
+}
+
+/// Store all replicas see the same lease in
+#[tonic::async_trait]
+pub trait LeaseStore: Send + Sync {
+    /// Stores `candidate` lease unless other replica holds a valid one, returns the resulting lease
+    async fn acquire(&self, candidate: &Lease, now: DateTime<Utc>) -> BoxResult<Lease>;
+}
+
+/// Lease kept in memory, for replicas in one process
+#[derive(Default)]
+pub struct LocalLeaseStore(std::sync::Mutex<Option<Lease>>);
+
+#[tonic::async_trait]
+impl LeaseStore for LocalLeaseStore {
+    async fn acquire(&self, candidate: &Lease, now: DateTime<Utc>) -> BoxResult<Lease> {
+        let mut current = self.0.lock().map_err(|e| e.to_string())?;
+        let lease = Lease::resolve(current.take(), candidate, now);
+        *current = Some(lease.clone());
+        Ok(lease)
+    }
+}
+
+/// Lease kept in a JSON file on storage shared by replicas, updates are serialized by a lock file
+pub struct FileLeaseStore {
+    path: PathBuf,
+}
+
+impl FileLeaseStore {
+    /// Lock file left by a crashed replica is removed after this time
+    const STALE_LOCK: Duration = Duration::from_secs(10);
+
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    async fn lock(&self) -> BoxResult<FileLock> {
+        let path = self.path.with_extension("lock");
+        for _ in 0..100 {
+            match OpenOptions::new().write(true).create_new(true).open(&path).await {
+                Ok(_) => return Ok(FileLock(path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(&path).await?.modified()?.elapsed().unwrap_or_default();
+                    if age > Self::STALE_LOCK {
+                        error!("Removing stale lease lock '{}'", path.display());
+                        let _ = fs::remove_file(&path).await;
+                    } else {
+                        tokio::time::delay_for(Duration::from_millis(10)).await;
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(format!("Can't lock '{}'", path.display()).into())
+    }
+}
+
+#[tonic::async_trait]
+impl LeaseStore for FileLeaseStore {
+    async fn acquire(&self, candidate: &Lease, now: DateTime<Utc>) -> BoxResult<Lease> {
+        let _lock = self.lock().await?;
+        let current = match fs::read(&self.path).await {
+            Ok(x) => serde_json::from_slice(&x).ok(),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        let lease = Lease::resolve(current, candidate, now);
+        if &lease == candidate {
+            // Renamed file is never seen half written
+            let tmp = self.path.with_extension("tmp");
+            fs::write(&tmp, serde_json::to_vec(&lease)?).await?;
+            fs::rename(&tmp, &self.path).await?;
+        }
+        Ok(lease)
+    }
+}
+
+/// Removes lock file when dropped
+struct FileLock(PathBuf);
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Role of this replica, shared with RPC and web handlers
+pub struct Leadership {
+    identity: String,
+    address: String,
+    /// Lease seen on the last campaign
+    lease: std::sync::Mutex<Option<Lease>>,
+    /// Replica runs without election and is always leader
+    standalone: bool,
+}
+
+impl Leadership {
+    /// Single replica without election
+    pub fn standalone() -> Self {
+        Self {
+            identity: String::new(),
+            address: String::new(),
+            lease: Default::default(),
+            standalone: true,
+        }
+    }
+
+    /// Replica identified by `identity` that agents reach at `address`
+    pub fn new(identity: String, address: String) -> Self {
+        Self { identity, address, lease: Default::default(), standalone: false }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.standalone
+            || self.lease().map_or(false, |x| x.holder == self.identity && x.expires_at > Utc::now())
+    }
+
+    /// Address of the leader, none if unknown
+    pub fn leader_address(&self) -> Option<String> {
+        if self.standalone {
+            return Some(self.address.clone());
+        }
+        self.lease().filter(|x| x.expires_at > Utc::now()).map(|x| x.address)
+    }
+
+    fn lease(&self) -> Option<Lease> {
+        self.lease.lock().ok()?.clone()
+    }
+
+    /// Campaigns for the lease valid for `ttl` and records the result
+    async fn campaign(&self, store: &dyn LeaseStore, ttl: Duration) -> BoxResult<()> {
+        let now = Utc::now();
+        let candidate = Lease {
+            holder: self.identity.clone(),
+            address: self.address.clone(),
+            expires_at: now + chrono::Duration::from_std(ttl)?,
+        };
+        let lease = store.acquire(&candidate, now).await?;
+        *self.lease.lock().map_err(|e| e.to_string())? = Some(lease);
+        Ok(())
+    }
+}
+
+/// Keeps campaigning for the lease, renewing it three times per `ttl`. Replica becoming leader
+/// restores state from `data_dir`, leader that lost the lease steps down and keeps campaigning
+/// as passive replica.
+pub async fn run(
+    store: Box<dyn LeaseStore>,
+    ttl: Duration,
+    leadership: Arc<Leadership>,
+    scheduler: Arc<Mutex<Scheduler>>,
+    data_dir: PathBuf,
+) {
+    let mut interval = tokio::time::interval(ttl / 3);
+    loop {
+        interval.tick().await;
+        let was_leader = leadership.is_leader();
+        if let Err(e) = leadership.campaign(store.as_ref(), ttl).await {
+            error!("Can't campaign for leadership: {}", e);
+        }
+        match (was_leader, leadership.is_leader()) {
+            (false, true) => {
+                debug!("Replica '{}' became leader", leadership.identity);
+                scheduler.lock().await.restore(&data_dir);
+            }
+            (true, false) => {
+                error!("Replica '{}' lost leadership, stepping down", leadership.identity);
+                scheduler.lock().await.step_down();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn lease(holder: &str, expires_at: DateTime<Utc>) -> Lease {
+        Lease { holder: holder.to_string(), address: format!("http://{}:50051", holder), expires_at }
+    }
+
+    async fn failover(store: &dyn LeaseStore) {
+        let now = Utc.ymd(2020, 5, 1).and_hms(12, 0, 0);
+        let later = now + chrono::Duration::seconds(15);
+        let a = lease("a", later);
+        let b = lease("b", later);
+        assert_eq!(store.acquire(&a, now).await.unwrap(), a);
+        assert_eq!(store.acquire(&b, now).await.unwrap(), a);
+        // Leader renews, the other replica takes over only after the lease expires
+        let renewed = lease("a", later + chrono::Duration::seconds(5));
+        assert_eq!(store.acquire(&renewed, now).await.unwrap(), renewed);
+        let takeover = lease("b", later + chrono::Duration::seconds(30));
+        let expired = later + chrono::Duration::seconds(6);
+        assert_eq!(store.acquire(&takeover, expired).await.unwrap(), takeover);
+    }
+
+    #[tokio::test]
+    async fn local_failover() {
+        failover(&LocalLeaseStore::default()).await;
+    }
+
+    #[tokio::test]
+    async fn file_failover() {
+        let path = std::env::temp_dir().join(format!("lease-{}.json", Uuid::new_v4()));
+        failover(&FileLeaseStore::new(path.clone())).await;
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,32 @@
+#![deny(warnings)]
+#![feature(const_fn, try_trait, async_closure)]
+#![deny(clippy::all, clippy::pedantic)]
+#![allow(
+    clippy::default_trait_access,
+    clippy::missing_errors_doc,
+    clippy::module_name_repetitions,
+    clippy::must_use_candidate,
+    clippy::too_many_arguments,
+    clippy::too_many_lines,
+    clippy::type_complexity,
+    clippy::use_self,
+    clippy::single_match_else,
+    clippy::wildcard_imports,
+)]
+
+pub mod config;
+pub mod ha;
+pub mod metrics;
+pub mod rpc;
+pub mod scheduler;
+pub mod simulator;
+pub mod webui;
+
+mod prelude {
+    pub(crate) use {
+        log::debug, log::error, log::trace, rust_decimal::Decimal,
+        serde::Deserialize, serde::Serialize, std::collections::HashMap, std::path::Path, std::str::FromStr,
+        std::sync::Arc, tokio::sync::Mutex, uuid::Uuid, std::convert::TryInto,
+    };
+    pub type BoxResult<T> = Result<T, Box<dyn std::error::Error>>;
+}
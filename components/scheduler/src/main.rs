@@ -1,5 +1,4 @@
 #![deny(warnings)]
-#![feature(const_fn, try_trait, async_closure)]
 #![deny(clippy::all, clippy::pedantic)]
 #![allow(
     clippy::default_trait_access,
@@ -14,34 +13,143 @@
     clippy::wildcard_imports,
 )]
 
-mod rpc;
-mod scheduler;
-mod webui;
-
 use futures_util::future::FutureExt;
+use scheduler::config::{Config, TlsConfig};
+use scheduler::{ha, metrics, rpc, webui};
 use std::error::Error;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use tonic::transport::Server;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+
+/// Time replica holds leadership without renewing it
+const LEASE_TTL: Duration = Duration::from_secs(15);
+/// Period of writing learned state to the data directory
+const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let config = Config::from_args()?;
+    setup_logger(&config)?;
+    let mut runtime = tokio::runtime::Builder::new()
+        .threaded_scheduler()
+        .core_threads(config.core_threads)
+        .enable_all()
+        .build()?;
+    runtime.block_on(run(config))
+}
+
+async fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    // Spans are exported by a task of the runtime
+    telemetry::init(config.trace_exporter, &config.otlp_endpoint, "scheduler");
+    let data_dir = config.data_dir.clone();
+    let mut scheduler = scheduler::scheduler::Scheduler::new(&data_dir);
+    scheduler.set_solver(config.solver);
+    if let Some(weights) = config.weights.clone() {
+        scheduler.set_weights(weights).await;
+    }
+    let scheduler = Arc::new(Mutex::new(scheduler));
 
-#[tokio::main(core_threads = 4)]
-async fn main() -> Result<(), Box<dyn Error>> {
-    setup_logger()?;
-    let scheduler = Arc::new(Mutex::new(scheduler::Scheduler::new()));
+    // Replicas sharing lease file elect the leader, without it the replica is always leader
+    let (leadership, election) = match &config.lease_file {
+        Some(path) => {
+            let identity =
+                config.replica_id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            let leadership =
+                Arc::new(ha::Leadership::new(identity, config.advertise_address.clone()));
+            let store = Box::new(ha::FileLeaseStore::new(path.clone()));
+            let election =
+                ha::run(store, LEASE_TTL, leadership.clone(), scheduler.clone(), data_dir.clone());
+            (leadership, election.boxed_local())
+        }
+        None => (Arc::new(ha::Leadership::standalone()), futures::future::pending().boxed_local()),
+    };
 
-    let http_server = webui::serve(scheduler.clone());
+    let http_server = webui::serve(
+        scheduler.clone(),
+        leadership.clone(),
+        config.web_address,
+        config.tls.clone(),
+    );
 
-    let addr = "[::]:50051".parse()?;
-    let rpc_server = Server::builder()
-        .add_service(rpc::SchedulerServer::new(rpc::SchedulerService::new(scheduler.clone())))
-        .serve(addr)
+    let mut server = Server::builder();
+    if let Some(tls) = &config.tls {
+        server = server.tls_config(server_tls(tls)?);
+    }
+    let rpc_server = server
+        .add_service(metrics::RpcMetrics(rpc::SchedulerServer::new(
+            rpc::SchedulerService::new(scheduler.clone(), leadership.clone()),
+        )))
+        .serve(config.rpc_address)
         .map(|_| ());
 
-    futures::join!(http_server, rpc_server);
+    // Starts runs of scheduled tasks once they are due
+    let timer = async {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if leadership.is_leader() {
+                scheduler.lock().await.run_scheduled_tasks().await;
+            }
+        }
+    };
+
+    // Rounds reacting to changing task profiles besides the ones triggered by cluster changes
+    let rounds = async {
+        let period = match config.schedule_interval {
+            Some(seconds) => Duration::from_secs(seconds),
+            None => return futures::future::pending::<()>().await,
+        };
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if leadership.is_leader() {
+                scheduler.lock().await.schedule().await;
+            }
+        }
+    };
+
+    // State is written periodically instead of on every change, only by the leader as passive
+    // replicas would overwrite it with stale state
+    let persistence = async {
+        let mut interval = tokio::time::interval(PERSIST_INTERVAL);
+        loop {
+            interval.tick().await;
+            if leadership.is_leader() {
+                persist(&scheduler).await;
+            }
+        }
+    };
+
+    let services =
+        async { futures::join!(http_server, rpc_server, timer, rounds, election, persistence) };
+    tokio::select! {
+        _ = services => {}
+        _ = tokio::signal::ctrl_c() => if leadership.is_leader() {
+            persist(&scheduler).await
+        },
+    }
     Ok(())
 }
 
-fn setup_logger() -> Result<(), fern::InitError> {
+/// Writes state changed since the last call, files are written after the lock is released
+async fn persist(scheduler: &Mutex<scheduler::scheduler::Scheduler>) {
+    let snapshots = scheduler.lock().await.snapshots();
+    for snapshot in snapshots {
+        snapshot.write().await;
+    }
+}
+
+/// Server identity, agents are verified against the certificate authority when it's given
+fn server_tls(tls: &TlsConfig) -> Result<ServerTlsConfig, Box<dyn Error>> {
+    let identity = Identity::from_pem(std::fs::read(&tls.cert)?, std::fs::read(&tls.key)?);
+    let mut config = ServerTlsConfig::new().identity(identity);
+    if let Some(ca) = &tls.ca {
+        config = config.client_ca_root(Certificate::from_pem(std::fs::read(ca)?));
+    }
+    Ok(config)
+}
+
+fn setup_logger(config: &Config) -> Result<(), Box<dyn Error>> {
     use fern::colors::ColoredLevelConfig;
     let colors = ColoredLevelConfig::new()
         .debug(fern::colors::Color::Green)
@@ -57,18 +165,9 @@ fn setup_logger() -> Result<(), fern::InitError> {
                 message
             ))
         })
-        .level(log::LevelFilter::Info)
-        .level_for("scheduler", log::LevelFilter::Trace)
+        .level(config.log_level.parse()?)
+        .level_for("scheduler", config.scheduler_log_level.parse()?)
         .chain(std::io::stderr())
         .apply()?;
     Ok(())
 }
-
-mod prelude {
-    pub(crate) use {
-        log::debug, log::error, log::trace, rust_decimal::Decimal,
-        serde::Serialize, std::collections::HashMap, std::path::Path, std::str::FromStr,
-        std::sync::Arc, tokio::sync::Mutex, uuid::Uuid, std::convert::TryInto,
-    };
-    pub type BoxResult<T> = Result<T, Box<dyn std::error::Error>>;
-}
@@ -0,0 +1,126 @@
+//! Prometheus metrics of the scheduler, web UI serves them at `/metrics`
+
+use crate::prelude::*;
+use crate::scheduler::{RoundEvent, Scheduler};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, Histogram, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    TextEncoder,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tonic::codegen::{http, Service};
+use tonic::transport::NamedService;
+
+lazy_static! {
+    static ref TASKS: IntGaugeVec =
+        register_int_gauge_vec!("scheduler_tasks", "Tasks by status", &["status"]).unwrap();
+    static ref SERVERS: IntGaugeVec =
+        register_int_gauge_vec!("scheduler_servers", "Servers by state", &["state"]).unwrap();
+    static ref SOLVE_DURATION: Histogram = register_histogram!(
+        "scheduler_solve_duration_seconds",
+        "Time spent solving the flow graph of a round",
+        prometheus::exponential_buckets(0.001, 2.0, 16).unwrap()
+    )
+    .unwrap();
+    static ref GRAPH_NODES: IntGauge =
+        register_int_gauge!("scheduler_graph_nodes", "Nodes of the last flow graph").unwrap();
+    static ref GRAPH_EDGES: IntGauge =
+        register_int_gauge!("scheduler_graph_edges", "Edges of the last flow graph").unwrap();
+    static ref MIGRATIONS: IntCounter =
+        register_int_counter!("scheduler_migrations_total", "Tasks moved between servers").unwrap();
+    static ref RPC_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "scheduler_rpc_errors_total",
+        "gRPC calls that returned an error",
+        &["method", "code"]
+    )
+    .unwrap();
+}
+
+fn to_i64(x: usize) -> i64 {
+    x.try_into().unwrap_or(i64::MAX)
+}
+
+/// Records solver statistics and migrations of a round
+pub fn observe_round(round: &RoundEvent) {
+    SOLVE_DURATION.observe(round.solve_duration.as_secs_f64());
+    GRAPH_NODES.set(to_i64(round.nodes));
+    GRAPH_EDGES.set(to_i64(round.edges));
+    let moved = round.decisions.iter().filter(|x| x.decision == crate::scheduler::Decision::Moved);
+    MIGRATIONS.inc_by(to_i64(moved.count()));
+}
+
+/// gRPC service counting calls that returned an error, failed calls carry their status in
+/// response headers.
+///
+/// Status sent in trailers isn't seen, so streaming calls like `subscribe_tasks` are counted
+/// only when they fail before the stream starts.
+#[derive(Clone)]
+pub struct RpcMetrics<S>(pub S);
+
+impl<S: NamedService> NamedService for RpcMetrics<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S, B, R> Service<http::Request<B>> for RpcMetrics<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<R>> + 'static,
+    S::Future: Send + 'static,
+    B: 'static,
+    R: 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        // Path is /package.Service/Method
+        let method = request.uri().path().rsplit('/').next().map(snake_case).unwrap_or_default();
+        let response = self.0.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            let code = response.headers().get("grpc-status").and_then(|x| x.to_str().ok());
+            match code.and_then(|x| x.parse().ok()).map(tonic::Code::from_i32) {
+                Some(tonic::Code::Ok) | None => {}
+                Some(code) => {
+                    RPC_ERRORS.with_label_values(&[&method, &format!("{:?}", code)]).inc();
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut result = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            result.push('_');
+        }
+        result.extend(c.to_lowercase());
+    }
+    result
+}
+
+/// Metrics in Prometheus text format, task and server gauges are taken from `scheduler`
+pub fn gather(scheduler: &Scheduler) -> BoxResult<Vec<u8>> {
+    TASKS.reset();
+    for (status, count) in scheduler.task_counts() {
+        TASKS.with_label_values(&[&format!("{:?}", status).to_lowercase()]).set(to_i64(count));
+    }
+    SERVERS.reset();
+    let now = scheduler.now();
+    for server in scheduler.get_servers() {
+        let state = format!("{:?}", server.effective_state(now)).to_lowercase();
+        SERVERS.with_label_values(&[&state]).inc();
+    }
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(buffer)
+}
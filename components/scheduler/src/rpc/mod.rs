@@ -1,8 +1,11 @@
+use crate::ha::Leadership;
 use crate::prelude::*;
 use crate::scheduler;
+use crate::scheduler::dimension;
 use futures::channel::mpsc;
 use futures::{FutureExt, StreamExt};
 use log::debug;
+use telemetry::TraceSpan;
 use tonic::{Request, Response, Status};
 
 pub use proto::scheduler_server::{Scheduler, SchedulerServer};
@@ -15,11 +18,21 @@ type SchedulerObj = Arc<Mutex<scheduler::Scheduler>>;
 
 pub struct SchedulerService {
     scheduler: SchedulerObj,
+    leadership: Arc<Leadership>,
 }
 
 impl SchedulerService {
-    pub fn new(scheduler: SchedulerObj) -> Self {
-        Self { scheduler }
+    pub fn new(scheduler: SchedulerObj, leadership: Arc<Leadership>) -> Self {
+        Self { scheduler, leadership }
+    }
+
+    /// Passive replicas refuse calls changing state, agents have to reconnect to the leader
+    fn check_leader(&self) -> Result<(), Status> {
+        if self.leadership.is_leader() {
+            return Ok(());
+        }
+        let leader = self.leadership.leader_address().unwrap_or_default();
+        Err(Status::unavailable(format!("Replica is not leader, leader is '{}'", leader)))
     }
 }
 
@@ -31,17 +44,18 @@ impl Scheduler for SchedulerService {
         &self,
         request: Request<RegistrationRequest>,
     ) -> Result<Response<RegistrationReply>, Status> {
+        self.check_leader()?;
         let request = request.into_inner();
         debug!("Registering server id: '{}'", request.machine_id);
-        self.scheduler
-            .lock()
-            .await
-            .insert_server(scheduler::Server::new(
-                Uuid::parse_str(&request.machine_id).unwrap(),
-                request.hostname.clone(),
-                None,
-            ))
-            .await;
+        let mut server = scheduler::Server::new(
+            Uuid::parse_str(&request.machine_id).unwrap(),
+            request.hostname.clone(),
+            None,
+        );
+        server
+            .set_labels(request.labels.into_iter().collect())
+            .set_capacity(request.capacity.map(Into::into));
+        self.scheduler.lock().await.insert_server(server).await;
 
         let reply = proto::RegistrationReply { should_benchmark: true };
 
@@ -52,12 +66,22 @@ impl Scheduler for SchedulerService {
         &self,
         request: Request<proto::BenchmarkSubmitRequest>,
     ) -> Result<Response<proto::BenchmarkSubmitReply>, Status> {
+        self.check_leader()?;
         let request = request.into_inner();
         debug!("Received benchmark from server id: '{}'", request.machine_id);
         let mut sch = self.scheduler.lock().await;
         let server = sch.get_server(&Uuid::from_str(&request.machine_id).unwrap()).unwrap();
         debug!("Registering server with profile: '{:?}'", server);
-        server.set_profile(Some(request.profile.unwrap().into()));
+        let profile: scheduler::ResourceProfile = request.profile.unwrap().into();
+        // Bandwidth achieved by benchmark is the best estimate of disk and network capacity
+        if let Some(mut capacity) = *server.capacity() {
+            use rust_decimal::prelude::ToPrimitive;
+
+            capacity.disk = profile.get(dimension::DISK).to_u64().unwrap_or_default();
+            capacity.network = profile.get(dimension::NETWORK).to_u64().unwrap_or_default();
+            server.set_capacity(Some(capacity));
+        }
+        server.set_profile(Some(profile));
         sch.schedule().await;
         let reply = proto::BenchmarkSubmitReply {};
 
@@ -68,6 +92,7 @@ impl Scheduler for SchedulerService {
         &self,
         request: Request<proto::SubscribeTasksRequest>,
     ) -> Result<Response<Self::SubscribeTasksStream>, tonic::Status> {
+        self.check_leader()?;
         let (sched_tx, sched_rx) = mpsc::channel(10);
 
         let request = request.into_inner();
@@ -101,44 +126,134 @@ impl Scheduler for SchedulerService {
         &self,
         request: Request<tonic::Streaming<proto::StreamTaskProfilesRequest>>,
     ) -> Result<Response<proto::StreamTaskProfilesReply>, Status> {
+        self.check_leader()?;
         let mut stream = request.into_inner();
         while let Some(request) = stream.next().await {
             let request = request?;
             let mut sched = self.scheduler.lock().await;
-            let task = sched.get_task(&Uuid::from_str(&request.task_id).unwrap()).unwrap();
             trace!("Received profile for '{}', '{:?}'", &request.task_id, &request.profile);
-            task.insert_profile(
+            if let Err(e) = sched.insert_task_profile(
+                &Uuid::from_str(&request.task_id).unwrap(),
                 Uuid::from_str(&request.machine_id).unwrap(),
                 request.profile.unwrap().into(),
-            );
+            ) {
+                error!("Can't store profile of task '{}': {}", request.task_id, e);
+            }
             sched.schedule().await;
         }
         Ok(Response::new(proto::StreamTaskProfilesReply {}))
     }
 
+    async fn plan_schedule(
+        &self,
+        request: Request<proto::PlanScheduleRequest>,
+    ) -> Result<Response<proto::PlanScheduleReply>, Status> {
+        let request = request.into_inner();
+        let invalid = |e: &dyn std::fmt::Display| Status::invalid_argument(e.to_string());
+        let tasks = request
+            .tasks
+            .into_iter()
+            .map(|x| {
+                let mut task = scheduler::Task::new(x.name, None, x.image, false, x.cmd);
+                task.set_requests(x.requests.map(Into::into));
+                task
+            })
+            .collect();
+        let drain = request
+            .drain_servers
+            .iter()
+            .map(|x| Uuid::parse_str(x))
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid(&e))?;
+        let weights = if request.weights.is_empty() {
+            None
+        } else {
+            Some(serde_json::from_str(&request.weights).map_err(|e| invalid(&e))?)
+        };
+        let scenario = scheduler::Scenario { tasks, drain, weights };
+        let plan = self.scheduler.lock().await.plan(&scenario);
+        Ok(Response::new(plan.into()))
+    }
+
+    async fn accept_task(
+        &self,
+        request: Request<proto::AcceptTaskRequest>,
+    ) -> Result<Response<proto::AcceptTaskReply>, Status> {
+        self.check_leader()?;
+        let request = request.into_inner();
+        let task = Uuid::from_str(&request.task_id)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let context = self.scheduler.lock().await.accept_task(&task);
+        let span = TraceSpan::start("accept_task", &context);
+        span.set_attribute("task", request.task_id);
+        span.set_attribute("server", request.machine_id);
+        let mut response = Response::new(proto::AcceptTaskReply {});
+        telemetry::inject_metadata(&span.carrier(), response.metadata_mut());
+        Ok(response)
+    }
+
     async fn finish_task(
         &self,
         request: Request<proto::FinishTaskRequest>,
     ) -> Result<Response<proto::FinishTaskReply>, Status> {
+        self.check_leader()?;
+        let context = telemetry::extract_metadata(request.metadata());
+        let _span = TraceSpan::start("finish_task", &context);
         let request = request.into_inner();
 
         let mut sched = self.scheduler.lock().await;
-        sched.get_task(&Uuid::from_str(&request.task_id).unwrap()).unwrap().set_schedulable(false);
-        sched.schedule().await;
+        sched.finish_task(&Uuid::from_str(&request.task_id).unwrap(), request.exit_code).await;
         Ok(Response::new(proto::FinishTaskReply {}))
     }
+
+    async fn confirm_removal(
+        &self,
+        request: Request<proto::ConfirmRemovalRequest>,
+    ) -> Result<Response<proto::ConfirmRemovalReply>, Status> {
+        self.check_leader()?;
+        let request = request.into_inner();
+        let server = Uuid::from_str(&request.machine_id)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let task = Uuid::from_str(&request.task_id)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        self.scheduler.lock().await.confirm_removal(&server, &task);
+        Ok(Response::new(proto::ConfirmRemovalReply {}))
+    }
+
+    async fn get_leader(
+        &self,
+        _request: Request<proto::GetLeaderRequest>,
+    ) -> Result<Response<proto::GetLeaderReply>, Status> {
+        Ok(Response::new(proto::GetLeaderReply {
+            address: self.leadership.leader_address().unwrap_or_default(),
+            is_leader: self.leadership.is_leader(),
+        }))
+    }
 }
 
 impl Into<scheduler::ResourceProfile> for proto::Profile {
     fn into(self) -> scheduler::ResourceProfile {
-        scheduler::ResourceProfile {
-            ipc: Decimal::new(self.instructions.try_into().unwrap(), 0)
-                .checked_div(Decimal::new(self.cycles.try_into().unwrap(), 0))
-                .unwrap_or_else(|| Decimal::new(0, 0)),
-            disk: self.vfs_read + self.vfs_write,
-            memory: self.memory,
-            network: self.tcp_send_bytes + self.tcp_recv_bytes,
-        }
+        let ratio = |a: u64, b: u64| Decimal::from(a).checked_div(Decimal::from(b)).unwrap_or_default();
+        vec![
+            (dimension::IPC, ratio(self.instructions, self.cycles)),
+            (dimension::DISK, Decimal::from(self.vfs_read + self.vfs_write)),
+            (dimension::MEMORY, Decimal::from(self.memory)),
+            (dimension::NETWORK, Decimal::from(self.tcp_send_bytes + self.tcp_recv_bytes)),
+            (dimension::CACHE_MISSES, Decimal::from(self.cache_misses)),
+            (dimension::CACHE_REFERENCES, Decimal::from(self.cache_references)),
+            (dimension::L1_DCACHE_LOADS, Decimal::from(self.l1_dcache_loads)),
+            (dimension::L1_DCACHE_LOAD_MISSES, Decimal::from(self.l1_dcache_load_misses)),
+            (dimension::L1_ICACHE_LOAD_MISSES, Decimal::from(self.l1_icache_load_misses)),
+            (dimension::LLC_LOADS, Decimal::from(self.llc_loads)),
+            (dimension::LLC_LOAD_MISSES, Decimal::from(self.llc_load_misses)),
+            (dimension::CYCLES, Decimal::from(self.cycles)),
+            (
+                dimension::CACHE_PRESSURE,
+                ratio(self.llc_load_misses, self.cycles) * Decimal::from(1000),
+            ),
+        ]
+        .into_iter()
+        .collect()
     }
 }
 
@@ -149,6 +264,50 @@ impl From<scheduler::Task<scheduler::ResourceProfile>> for proto::subscribe_task
             image: task.image().clone(),
             is_profiled: task.request().is_none(),
             cmd: task.cmd().clone(),
+            limits: task.limits().map(Into::into),
+            volume: task.volume().clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<scheduler::Plan> for proto::PlanScheduleReply {
+    fn from(plan: scheduler::Plan) -> Self {
+        Self {
+            schedule: plan.schedule.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            cost: plan.cost,
+            placed: plan.placed.iter().map(ToString::to_string).collect(),
+            moved: plan
+                .moved
+                .iter()
+                .map(|x| proto::plan_schedule_reply::Move {
+                    task_id: x.task.to_string(),
+                    from: x.from.to_string(),
+                    to: x.to.to_string(),
+                })
+                .collect(),
+            descheduled: plan.descheduled.iter().map(ToString::to_string).collect(),
+        }
+    }
+}
+
+impl From<proto::Resources> for scheduler::Resources {
+    fn from(resources: proto::Resources) -> Self {
+        Self {
+            cpu_millis: resources.cpu_millis,
+            memory: resources.memory,
+            disk: resources.disk,
+            network: resources.network,
+        }
+    }
+}
+
+impl From<scheduler::Resources> for proto::Resources {
+    fn from(resources: scheduler::Resources) -> Self {
+        Self {
+            cpu_millis: resources.cpu_millis,
+            memory: resources.memory,
+            disk: resources.disk,
+            network: resources.network,
         }
     }
 }
@@ -0,0 +1,142 @@
+use super::NormalizedResourceProfile;
+use crate::prelude::*;
+use std::collections::BTreeSet;
+
+/// How profile samples of a task are combined into a single profile
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Aggregation {
+    /// Exponentially weighted moving average, `alpha` is the weight of the newest sample
+    Ewma { alpha: Decimal },
+    /// Percentile of each resource, 50 for median, 100 for maximum
+    Percentile { percentile: u8 },
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct AggregationPolicy {
+    pub method: Aggregation,
+    /// Number of newest samples kept per task and server
+    pub retention: usize,
+    /// Number of samples compared before and after a possible phase change
+    pub phase_window: usize,
+    /// Change of a normalized resource between windows that is considered a new phase,
+    /// phase detection is disabled if zero
+    pub phase_threshold: Decimal,
+}
+
+impl Default for AggregationPolicy {
+    fn default() -> Self {
+        Self {
+            method: Aggregation::Ewma { alpha: Decimal::new(3, 1) },
+            retention: 120,
+            phase_window: 4,
+            phase_threshold: Decimal::new(25, 2),
+        }
+    }
+}
+
+impl AggregationPolicy {
+    /// Aggregates samples of the current phase, samples are ordered from the oldest
+    pub fn aggregate(&self, samples: &[NormalizedResourceProfile]) -> Option<NormalizedResourceProfile> {
+        let samples = self.current_phase(samples);
+        if samples.is_empty() {
+            return None;
+        }
+        match self.method {
+            Aggregation::Ewma { alpha } => Some(ewma(samples, alpha)),
+            Aggregation::Percentile { percentile } => Some(self::percentile(samples, percentile)),
+        }
+    }
+
+    /// Samples since the latest phase change. Phase changes when mean of some resource in
+    /// `phase_window` samples differs from the mean of preceding window by more than threshold
+    pub fn current_phase<'a>(
+        &self,
+        samples: &'a [NormalizedResourceProfile],
+    ) -> &'a [NormalizedResourceProfile] {
+        let window = self.phase_window;
+        if self.phase_threshold.is_zero() || window == 0 || samples.len() < 2 * window {
+            return samples;
+        }
+        for start in (window..=samples.len() - window).rev() {
+            let before = mean(&samples[start - window..start]);
+            let after = mean(&samples[start..start + window]);
+            let changed = dimensions(&[before.clone(), after.clone()])
+                .iter()
+                .any(|x| (after.get(x) - before.get(x)).abs() > self.phase_threshold);
+            if changed {
+                return &samples[start..];
+            }
+        }
+        samples
+    }
+}
+
+fn dimensions(samples: &[NormalizedResourceProfile]) -> BTreeSet<String> {
+    samples.iter().flat_map(|x| x.dimensions().map(|(k, _)| k.to_string())).collect()
+}
+
+pub fn mean(samples: &[NormalizedResourceProfile]) -> NormalizedResourceProfile {
+    let sum = samples.iter().cloned().fold(NormalizedResourceProfile::default(), |acc, x| acc + x);
+    sum / Decimal::from(samples.len() as u64)
+}
+
+fn ewma(samples: &[NormalizedResourceProfile], alpha: Decimal) -> NormalizedResourceProfile {
+    let mut iter = samples.iter().cloned();
+    let first = iter.next().unwrap_or_default();
+    iter.fold(first, |acc, x| acc * (Decimal::new(1, 0) - alpha) + x * alpha)
+}
+
+fn percentile(samples: &[NormalizedResourceProfile], percentile: u8) -> NormalizedResourceProfile {
+    dimensions(samples)
+        .into_iter()
+        .map(|dimension| {
+            let mut values: Vec<Decimal> = samples.iter().map(|x| x.get(&dimension)).collect();
+            values.sort();
+            let rank = (values.len() - 1) * usize::from(percentile.min(100)) / 100;
+            (dimension, values[rank])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn samples(values: &[i64]) -> Vec<NormalizedResourceProfile> {
+        values.iter().map(|x| vec![("ipc", Decimal::new(*x, 1))].into_iter().collect()).collect()
+    }
+
+    #[test]
+    fn percentiles() {
+        let samples = samples(&[5, 1, 3, 2, 4]);
+        let policy = |percentile| AggregationPolicy {
+            method: Aggregation::Percentile { percentile },
+            phase_threshold: Decimal::new(0, 0),
+            ..Default::default()
+        };
+        assert_eq!(policy(50).aggregate(&samples).unwrap().get("ipc"), Decimal::new(3, 1));
+        assert_eq!(policy(100).aggregate(&samples).unwrap().get("ipc"), Decimal::new(5, 1));
+        assert!(policy(50).aggregate(&[]).is_none());
+    }
+
+    #[test]
+    fn ewma_prefers_recent_samples() {
+        let policy = AggregationPolicy {
+            method: Aggregation::Ewma { alpha: Decimal::new(5, 1) },
+            phase_threshold: Decimal::new(0, 0),
+            ..Default::default()
+        };
+        let profile = policy.aggregate(&samples(&[0, 0, 8])).unwrap();
+        assert_eq!(profile.get("ipc"), Decimal::new(4, 1));
+    }
+
+    #[test]
+    fn phase_change_drops_old_samples() {
+        let policy = AggregationPolicy { phase_window: 2, ..Default::default() };
+        let samples = samples(&[1, 1, 1, 1, 9, 9, 9]);
+        assert_eq!(policy.current_phase(&samples).len(), 3);
+        let stable = [1, 1, 2, 1, 1];
+        assert_eq!(policy.current_phase(&self::samples(&stable)).len(), stable.len());
+    }
+}
@@ -0,0 +1,318 @@
+use super::{Node, Plan};
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// Log is rotated when it grows over this size, one rotated file is kept
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Change of task placement made by a round
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Placed,
+    Moved,
+    Descheduled,
+}
+
+/// Edge of the flow path a task took through the solved graph
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct EdgeCost {
+    pub source: String,
+    pub target: String,
+    pub cost: u64,
+}
+
+impl EdgeCost {
+    fn label(node: &cost_flow::Node<Node>) -> String {
+        match node {
+            cost_flow::Node::Source => "Source".to_string(),
+            cost_flow::Node::Sink => "Sink".to_string(),
+            cost_flow::Node::Node(node) => cost_flow::Graphable::name_label(node),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaskDecision {
+    pub task: Uuid,
+    pub name: String,
+    pub decision: Decision,
+    /// Server task ran on before the round
+    pub from: Option<Uuid>,
+    /// Server task runs on after the round
+    pub to: Option<Uuid>,
+    /// Edges of the task's path, their costs explain the decision
+    pub edges: Vec<EdgeCost>,
+}
+
+/// Solved flow graph of a round
+#[derive(Clone, Debug, Default)]
+pub struct Solution {
+    pub nodes: usize,
+    pub edges: usize,
+    pub duration: Duration,
+    /// (task, server) -> edges of the path, server is none for unscheduled path
+    paths: HashMap<(Uuid, Option<Uuid>), Vec<EdgeCost>>,
+}
+
+impl Solution {
+    pub fn new(graph: &cost_flow::Graph<Node>, duration: Duration) -> Self {
+        let mut paths = HashMap::new();
+        for path in graph.paths() {
+            let mut task = None;
+            let mut server = None;
+            for edge in &path.edges {
+                match &edge.source {
+                    cost_flow::Node::Node(Node::Server(s)) => server = Some(*s.id()),
+                    cost_flow::Node::Node(Node::Task(t)) => task = Some(*t.id()),
+                    _ => {}
+                }
+            }
+            if let Some(task) = task {
+                let edges = path
+                    .edges
+                    .iter()
+                    .map(|x| EdgeCost {
+                        source: EdgeCost::label(&x.source),
+                        target: EdgeCost::label(&x.target),
+                        cost: x.cost,
+                    })
+                    .collect();
+                paths.insert((task, server), edges);
+            }
+        }
+        Self {
+            nodes: graph.node_count(),
+            edges: graph.edge_count(),
+            duration,
+            paths,
+        }
+    }
+
+    /// Edges of the path of `task` to `server`, any path of the task if the flow didn't
+    /// reach the server because the placement was overridden after solving
+    fn path(&self, task: Uuid, server: Option<Uuid>) -> Vec<EdgeCost> {
+        self.paths
+            .get(&(task, server))
+            .or_else(|| self.paths.iter().find(|((t, _), _)| *t == task).map(|(_, x)| x))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Everything a scheduling round decided
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RoundEvent {
+    pub time: DateTime<Utc>,
+    /// Total cost of the flow
+    pub objective: u64,
+    pub solve_duration: Duration,
+    pub nodes: usize,
+    pub edges: usize,
+    pub decisions: Vec<TaskDecision>,
+}
+
+impl RoundEvent {
+    /// Event of `plan` replacing `old` schedule, `names` gives task names
+    pub fn new(
+        plan: &Plan,
+        old: &HashMap<Uuid, Uuid>,
+        solution: Solution,
+        names: impl Fn(&Uuid) -> String,
+        time: DateTime<Utc>,
+    ) -> Self {
+        let decision = |task: Uuid, kind, from: Option<Uuid>, to: Option<Uuid>| TaskDecision {
+            task,
+            name: names(&task),
+            decision: kind,
+            from,
+            to,
+            edges: solution.path(task, to),
+        };
+        let placed = plan
+            .placed
+            .iter()
+            .map(|x| decision(*x, Decision::Placed, None, plan.schedule.get(x).copied()));
+        let moved =
+            plan.moved.iter().map(|x| decision(x.task, Decision::Moved, Some(x.from), Some(x.to)));
+        let descheduled = plan
+            .descheduled
+            .iter()
+            .map(|x| decision(*x, Decision::Descheduled, old.get(x).copied(), None));
+        let decisions = placed.chain(moved).chain(descheduled).collect();
+        Self {
+            time,
+            objective: plan.cost,
+            solve_duration: solution.duration,
+            nodes: solution.nodes,
+            edges: solution.edges,
+            decisions,
+        }
+    }
+}
+
+/// Filter of logged rounds
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    /// Only rounds that changed placement of the task, with its decision only
+    pub task: Option<Uuid>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    /// Maximal number of latest rounds returned
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn apply(&self, mut event: RoundEvent) -> Option<RoundEvent> {
+        let before = self.since.map_or(false, |x| event.time < x);
+        if before || self.until.map_or(false, |x| event.time > x) {
+            return None;
+        }
+        if let Some(task) = self.task {
+            event.decisions.retain(|x| x.task == task);
+            if event.decisions.is_empty() {
+                return None;
+            }
+        }
+        Some(event)
+    }
+}
+
+enum Message {
+    /// Serialized event
+    Append(Vec<u8>),
+    /// Acknowledged once all previous events are written
+    Sync(mpsc::Sender<()>),
+}
+
+/// Rounds appended to a JSON lines file by a writer thread, so that rounds don't wait for disk
+/// while holding the scheduler lock
+#[derive(Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    writer: mpsc::Sender<Message>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        let (writer, messages) = mpsc::channel();
+        let writer_path = path.clone();
+        std::thread::spawn(move || write_events(&writer_path, &messages));
+        Self { path, writer }
+    }
+
+    /// Queues `event` for writing, write errors are logged by the writer
+    pub fn record(&self, event: &RoundEvent) -> BoxResult<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        self.writer.send(Message::Append(line)).map_err(|_| "audit writer stopped")?;
+        Ok(())
+    }
+
+    /// Rounds matching `query` oldest first, searched in the rotated and the current file after
+    /// all recorded rounds are written
+    pub fn query(&self, query: &AuditQuery) -> BoxResult<Vec<RoundEvent>> {
+        let (tx, rx) = mpsc::channel();
+        self.writer.send(Message::Sync(tx)).map_err(|_| "audit writer stopped")?;
+        rx.recv()?;
+        let mut events = VecDeque::new();
+        for path in &[rotated(&self.path), self.path.clone()] {
+            let file = match std::fs::File::open(path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                // Line cut by a crash while it was written is skipped
+                let event = match serde_json::from_str(&line) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        error!("Skipping corrupted audit line in '{}': {}", path.display(), e);
+                        continue;
+                    }
+                };
+                if let Some(event) = query.apply(event) {
+                    events.push_back(event);
+                    if query.limit.map_or(false, |x| events.len() > x) {
+                        events.pop_front();
+                    }
+                }
+            }
+        }
+        Ok(events.into())
+    }
+}
+
+/// File the log is moved to when it's rotated
+fn rotated(path: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.1", path.display()))
+}
+
+/// Appends events to `path` until all senders are dropped
+fn write_events(path: &Path, messages: &mpsc::Receiver<Message>) {
+    let append = |line: &[u8]| -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let size = std::fs::metadata(path).map(|x| x.len()).unwrap_or_default();
+        if size > 0 && size + line.len() as u64 > MAX_SIZE {
+            std::fs::rename(path, rotated(path))?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(line)
+    };
+    for message in messages {
+        match message {
+            Message::Append(line) => {
+                if let Err(e) = append(&line) {
+                    error!("Can't write audit log '{}': {}", path.display(), e);
+                }
+            }
+            Message::Sync(ack) => {
+                let _ = ack.send(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Move;
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn query_task_history() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", Uuid::new_v4()));
+        let log = AuditLog::new(path.clone());
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let (s1, s2) = (Uuid::new_v4(), Uuid::new_v4());
+        let names = |x: &Uuid| if *x == a { "a".to_string() } else { "b".to_string() };
+        let time = Utc.ymd(2020, 5, 1).and_hms(14, 0, 0);
+
+        let old = HashMap::new();
+        let plan = Plan::new(&old, vec![(a, s1), (b, s1)].into_iter().collect(), 10);
+        log.record(&RoundEvent::new(&plan, &old, Solution::default(), names, time)).unwrap();
+        let old = plan.schedule;
+        let plan = Plan::new(&old, vec![(a, s2), (b, s1)].into_iter().collect(), 8);
+        let moved_at = time + chrono::Duration::minutes(2);
+        log.record(&RoundEvent::new(&plan, &old, Solution::default(), names, moved_at)).unwrap();
+
+        let query = AuditQuery { task: Some(a), since: Some(moved_at), ..Default::default() };
+        let events = log.query(&query).unwrap();
+        let _ = std::fs::remove_file(path);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].objective, 8);
+        let decision = &events[0].decisions[0];
+        assert_eq!(decision.decision, Decision::Moved);
+        let (from, to) = (decision.from.unwrap(), decision.to.unwrap());
+        assert_eq!(Move { task: decision.task, from, to }, plan.moved[0]);
+    }
+}
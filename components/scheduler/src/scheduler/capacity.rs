@@ -0,0 +1,80 @@
+use crate::prelude::*;
+use derive_more::{Add, AddAssign, Display};
+
+/// Absolute amount of server resources, unlike `ResourceProfile` it is not relative
+/// to the best server in the cluster
+#[derive(
+    Default,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    Serialize,
+    Deserialize,
+    Add,
+    AddAssign,
+)]
+pub struct Resources {
+    /// Thousandths of a core
+    pub cpu_millis: u64,
+    /// Bytes
+    pub memory: u64,
+    /// Bytes per second
+    pub disk: u64,
+    /// Bytes per second
+    pub network: u64,
+}
+
+impl Resources {
+    pub fn saturating_sub(&self, other: &Resources) -> Resources {
+        Resources {
+            cpu_millis: self.cpu_millis.saturating_sub(other.cpu_millis),
+            memory: self.memory.saturating_sub(other.memory),
+            disk: self.disk.saturating_sub(other.disk),
+            network: self.network.saturating_sub(other.network),
+        }
+    }
+
+    /// Names of resources where `request` exceeds available resources
+    pub fn shortfall(&self, request: &Resources) -> Vec<&'static str> {
+        vec![
+            ("cpu", self.cpu_millis < request.cpu_millis),
+            ("memory", self.memory < request.memory),
+            ("disk", self.disk < request.disk),
+            ("network", self.network < request.network),
+        ]
+        .into_iter()
+        .filter(|(_, missing)| *missing)
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    pub fn fits(&self, request: &Resources) -> bool {
+        self.shortfall(request).is_empty()
+    }
+}
+
+/// Task was rejected because it can't be ever placed
+#[derive(Debug, Display)]
+#[display(fmt = "{}", _0)]
+pub struct AdmissionError(pub String);
+
+impl std::error::Error for AdmissionError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shortfall() {
+        let free = Resources { cpu_millis: 1000, memory: 1024, disk: 10, network: 10 };
+        let request = Resources { cpu_millis: 2000, memory: 512, disk: 10, network: 11 };
+        assert_eq!(free.shortfall(&request), vec!["cpu", "network"]);
+        assert!(free.fits(&Resources { cpu_millis: 1000, ..Default::default() }));
+        assert_eq!(free.saturating_sub(&request).cpu_millis, 0);
+    }
+}
@@ -0,0 +1,171 @@
+use super::dimension::{DISK, IPC, MEMORY, NETWORK};
+use super::{NormalizedResourceProfile, ResourceProfile, Snapshot};
+use crate::prelude::*;
+use std::path::PathBuf;
+
+/// Resource a workload is bound by
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkloadClass {
+    Cpu,
+    Memory,
+    Io,
+    Network,
+}
+
+impl WorkloadClass {
+    const ALL: [(WorkloadClass, &'static str); 4] = [
+        (WorkloadClass::Cpu, IPC),
+        (WorkloadClass::Memory, MEMORY),
+        (WorkloadClass::Io, DISK),
+        (WorkloadClass::Network, NETWORK),
+    ];
+
+    /// Class of the dimension the profile uses the largest share of
+    pub fn classify(profile: &NormalizedResourceProfile) -> Self {
+        Self::ALL
+            .iter()
+            .max_by_key(|(_, dimension)| profile.get(dimension))
+            .map_or(WorkloadClass::Cpu, |(class, _)| *class)
+    }
+}
+
+impl FromStr for WorkloadClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(WorkloadClass::Cpu),
+            "memory" => Ok(WorkloadClass::Memory),
+            "io" => Ok(WorkloadClass::Io),
+            "network" => Ok(WorkloadClass::Network),
+            _ => Err(format!("unknown workload class '{}'", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Entry {
+    mean: ResourceProfile,
+    samples: u64,
+}
+
+/// Mean profiles of past tasks by signature, used to predict profile of a task before it was
+/// profiled. Persisted across scheduler restarts.
+pub struct ProfileCatalog {
+    path: PathBuf,
+    entries: HashMap<String, Entry>,
+    /// Observed since the last snapshot
+    changed: bool,
+}
+
+impl ProfileCatalog {
+    /// Loads catalog from `path`, missing or corrupted file starts empty catalog
+    pub fn load(path: PathBuf) -> Self {
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|x| match serde_json::from_slice(&x) {
+                Ok(entries) => Some(entries),
+                Err(e) => {
+                    error!("Can't read profile catalog '{}': {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self { path, entries, changed: false }
+    }
+
+    /// Adds profile sample of a task with `signature` to its running mean
+    pub fn observe(&mut self, signature: &str, profile: &ResourceProfile) {
+        self.changed = true;
+        let entry = self.entries.entry(signature.to_string()).or_default();
+        entry.samples += 1;
+        let delta = (profile.clone() - entry.mean.clone()) / Decimal::from(entry.samples);
+        entry.mean += delta;
+    }
+
+    /// Class of tasks with `signature`, none if no such task was profiled
+    pub fn classify(&self, signature: &str, max_profile: &ResourceProfile) -> Option<WorkloadClass> {
+        let entry = self.entries.get(signature)?;
+        Some(WorkloadClass::classify(&entry.mean.normalize(max_profile)))
+    }
+
+    /// Predicted normalized profile of a task. Known signature uses its own history, otherwise
+    /// tasks of workload `class` get mean of all signatures in that class.
+    pub fn predict(
+        &self,
+        signature: &str,
+        class: Option<WorkloadClass>,
+        max_profile: &ResourceProfile,
+    ) -> Option<NormalizedResourceProfile> {
+        if let Some(entry) = self.entries.get(signature) {
+            return Some(entry.mean.normalize(max_profile));
+        }
+        let class = class?;
+        let members: Vec<_> = self
+            .entries
+            .values()
+            .map(|x| x.mean.normalize(max_profile))
+            .filter(|x| WorkloadClass::classify(x) == class)
+            .collect();
+        if members.is_empty() {
+            return None;
+        }
+        Some(super::aggregation::mean(&members))
+    }
+
+    /// Class and mean profile of every known signature
+    pub fn to_json(&self, max_profile: &ResourceProfile) -> serde_json::Value {
+        let entries: HashMap<_, _> = self
+            .entries
+            .iter()
+            .map(|(signature, entry)| {
+                let class = WorkloadClass::classify(&entry.mean.normalize(max_profile));
+                let json = serde_json::json!({
+                    "class": class,
+                    "profile": entry.mean,
+                    "samples": entry.samples,
+                });
+                (signature, json)
+            })
+            .collect();
+        serde_json::json!(entries)
+    }
+
+    /// Entries to be persisted, none if nothing was observed since the last snapshot
+    pub fn snapshot(&mut self) -> Option<Snapshot> {
+        if !std::mem::replace(&mut self.changed, false) {
+            return None;
+        }
+        Snapshot::new(&self.path, &self.entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn profile(values: Vec<(&str, i64)>) -> ResourceProfile {
+        values.into_iter().map(|(k, v)| (k, Decimal::new(v, 0))).collect()
+    }
+
+    #[test]
+    fn predicts_from_signature_and_class() {
+        let max = profile(vec![(IPC, 10), (MEMORY, 10), (DISK, 10), (NETWORK, 10)]);
+        let mut catalog = ProfileCatalog::load(PathBuf::from("/nonexistent/catalog.json"));
+        catalog.observe("db", &profile(vec![(IPC, 2), (DISK, 6)]));
+        catalog.observe("db", &profile(vec![(IPC, 2), (DISK, 8)]));
+        catalog.observe("encoder", &profile(vec![(IPC, 9), (MEMORY, 1)]));
+
+        let db = catalog.predict("db", None, &max).unwrap();
+        assert_eq!(db.get(DISK), Decimal::new(7, 1));
+        assert_eq!(WorkloadClass::classify(&db), WorkloadClass::Io);
+
+        assert_eq!(catalog.classify("db", &max), Some(WorkloadClass::Io));
+        assert_eq!(catalog.classify("new", &max), None);
+        let io = catalog.predict("new", Some(WorkloadClass::Io), &max).unwrap();
+        assert_eq!(io, db);
+        assert!(catalog.predict("new", Some(WorkloadClass::Network), &max).is_none());
+        assert!(catalog.predict("new", None, &max).is_none());
+    }
+}
@@ -0,0 +1,286 @@
+use super::{ResourceProfile, Resources, Task};
+use crate::prelude::*;
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+/// Number of past runs kept for every scheduled task
+const HISTORY_LIMIT: usize = 20;
+
+/// Five field cron expression: minute, hour, day of month, month and day of week. Fields accept
+/// `*`, values, ranges `a-b`, steps `*/n` or `a-b/n` and lists separated by commas.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CronExpression {
+    source: String,
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    /// Sunday is 0
+    weekdays: BTreeSet<u32>,
+}
+
+impl CronExpression {
+    /// First time matching the expression strictly after `after`, none if there is no such
+    /// time within next five years
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date();
+        for _ in 0..5 * 366 {
+            let weekday = date.weekday().num_days_from_sunday();
+            if self.months.contains(&date.month()) && self.matches_day(date.day(), weekday) {
+                for hour in &self.hours {
+                    for minute in &self.minutes {
+                        let time = date.and_hms(*hour, *minute, 0);
+                        if time >= start {
+                            return Some(time);
+                        }
+                    }
+                }
+            }
+            date = date.succ();
+        }
+        None
+    }
+
+    /// Like cron, day matches either field when both day of month and day of week are restricted
+    fn matches_day(&self, day: u32, weekday: u32) -> bool {
+        let days_restricted = self.days.len() < 31;
+        let weekdays_restricted = self.weekdays.len() < 7;
+        match (days_restricted, weekdays_restricted) {
+            (true, true) => self.days.contains(&day) || self.weekdays.contains(&weekday),
+            _ => self.days.contains(&day) && self.weekdays.contains(&weekday),
+        }
+    }
+}
+
+/// Parses cron field with values between `min` and `max`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<BTreeSet<u32>, String> {
+    let mut values = BTreeSet::new();
+    for part in field.split(',') {
+        let mut split = part.splitn(2, '/');
+        let range = split.next().unwrap_or_default();
+        let step = match split.next() {
+            Some(step) => step.parse::<usize>().map_err(|e| format!("invalid step '{}': {}", step, e))?,
+            None => 1,
+        };
+        let parse = |x: &str| x.parse::<u32>().map_err(|e| format!("invalid value '{}': {}", x, e));
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ if range.contains('-') => {
+                let mut bounds = range.splitn(2, '-');
+                (parse(bounds.next().unwrap_or_default())?, parse(bounds.next().unwrap_or_default())?)
+            }
+            // Single value with step runs until the end of the range
+            _ if part.contains('/') => (parse(range)?, max),
+            _ => (parse(range)?, parse(range)?),
+        };
+        if step == 0 || from > to || from < min || to > max {
+            return Err(format!("'{}' is out of range {}-{}", part, min, max));
+        }
+        values.extend((from..=to).step_by(step));
+    }
+    Ok(values)
+}
+
+impl FromStr for CronExpression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expanded = match s.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("cron expression '{}' doesn't have 5 fields", s));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekdays.remove(&7) {
+            weekdays.insert(0);
+        }
+        Ok(Self {
+            source: s.to_string(),
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+        })
+    }
+}
+
+impl TryFrom<String> for CronExpression {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<CronExpression> for String {
+    fn from(expression: CronExpression) -> Self {
+        expression.source
+    }
+}
+
+/// When scheduled task runs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// Repeatedly at times matching the expression
+    Cron(CronExpression),
+    /// Once at the given time, immediately if it already passed
+    At(DateTime<Utc>),
+}
+
+/// What happens when scheduled task is due while its previous run is still active
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConcurrencyPolicy {
+    /// Runs may overlap
+    Allow,
+    /// New run is skipped
+    Forbid,
+    /// Previous run is stopped and replaced by the new one
+    Replace,
+}
+
+impl Default for ConcurrencyPolicy {
+    fn default() -> Self {
+        ConcurrencyPolicy::Allow
+    }
+}
+
+/// Run of a scheduled task
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Run {
+    /// Time the run was due
+    pub scheduled_at: DateTime<Utc>,
+    /// Task instance, none if run was skipped or rejected
+    pub task: Option<Uuid>,
+    /// Why run was skipped or rejected
+    pub error: Option<String>,
+}
+
+/// Task template inserted by scheduler at times given by trigger
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledTask {
+    pub id: Uuid,
+    pub name: String,
+    pub trigger: Trigger,
+    pub concurrency: ConcurrencyPolicy,
+    pub image: String,
+    pub cmd: Option<String>,
+    pub realtime: bool,
+    pub request: Option<ResourceProfile>,
+    pub requests: Option<Resources>,
+    pub namespace: Option<String>,
+    /// Time of the next run, none when trigger won't fire anymore
+    pub next_run: Option<DateTime<Utc>>,
+    /// Past runs, oldest first
+    pub history: Vec<Run>,
+    next_instance: u64,
+}
+
+impl ScheduledTask {
+    pub fn new(
+        name: String,
+        trigger: Trigger,
+        concurrency: ConcurrencyPolicy,
+        image: String,
+        cmd: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let next_run = match &trigger {
+            Trigger::Cron(expression) => expression.next_after(now),
+            Trigger::At(time) => Some(*time),
+        };
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            trigger,
+            concurrency,
+            image,
+            cmd,
+            realtime: false,
+            request: None,
+            requests: None,
+            namespace: None,
+            next_run,
+            history: vec![],
+            next_instance: 0,
+        }
+    }
+
+    /// Continues history and instance numbering of `previous` scheduled task it replaces
+    pub fn inherit(&mut self, previous: ScheduledTask) {
+        self.history = previous.history;
+        self.next_instance = previous.next_instance;
+    }
+
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        self.next_run.map_or(false, |x| x <= now)
+    }
+
+    /// Tasks started by past runs
+    pub fn tasks(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.history.iter().filter_map(|x| x.task)
+    }
+
+    /// Creates task of the next run
+    pub fn instantiate(&mut self) -> Task<ResourceProfile> {
+        let mut task = Task::new(
+            format!("{}-{}", self.name, self.next_instance),
+            self.request.clone(),
+            self.image.clone(),
+            self.realtime,
+            self.cmd.clone(),
+        );
+        task.set_requests(self.requests);
+        if let Some(namespace) = &self.namespace {
+            task.set_namespace(namespace.clone());
+        }
+        self.next_instance += 1;
+        task
+    }
+
+    /// Records run that was due and computes time of the next one. Runs missed while
+    /// scheduler wasn't running are not caught up.
+    pub fn record(&mut self, run: Run, now: DateTime<Utc>) {
+        self.history.push(run);
+        let excess = self.history.len().saturating_sub(HISTORY_LIMIT);
+        self.history.drain(..excess);
+        self.next_run = match &self.trigger {
+            Trigger::Cron(expression) => expression.next_after(now),
+            Trigger::At(_) => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn next_run() {
+        let nightly: CronExpression = "30 2 * * *".parse().unwrap();
+        let after = Utc.ymd(2020, 5, 1).and_hms(2, 30, 0);
+        assert_eq!(nightly.next_after(after), Some(Utc.ymd(2020, 5, 2).and_hms(2, 30, 0)));
+
+        let weekdays: CronExpression = "*/15 9-17 * * 1-5".parse().unwrap();
+        // Friday evening rolls over to Monday morning
+        let after = Utc.ymd(2020, 5, 1).and_hms(17, 50, 0);
+        assert_eq!(weekdays.next_after(after), Some(Utc.ymd(2020, 5, 4).and_hms(9, 0, 0)));
+
+        assert!("60 * * * *".parse::<CronExpression>().is_err());
+        assert!("* * *".parse::<CronExpression>().is_err());
+    }
+}
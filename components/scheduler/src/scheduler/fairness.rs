@@ -0,0 +1,111 @@
+use super::{dimension, NormalizedResourceProfile};
+use crate::prelude::*;
+
+/// Resources dominant shares are computed over
+pub const DIMENSIONS: [&str; 4] =
+    [dimension::IPC, dimension::MEMORY, dimension::NETWORK, dimension::DISK];
+
+/// Resources allocated to a user by the current schedule
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Allocation {
+    pub user: String,
+    /// Number of running tasks
+    pub tasks: usize,
+    /// Summed normalized profiles of running tasks
+    pub usage: NormalizedResourceProfile,
+    /// Fraction of the cluster used for each resource
+    pub shares: NormalizedResourceProfile,
+    /// Resource with the largest share
+    pub dominant_resource: String,
+    pub dominant_share: Decimal,
+}
+
+impl Allocation {
+    /// Allocation of `user` running `tasks` with summed `usage` on cluster with `capacity`
+    pub fn new(
+        user: String,
+        tasks: usize,
+        usage: NormalizedResourceProfile,
+        capacity: &NormalizedResourceProfile,
+    ) -> Self {
+        let shares: NormalizedResourceProfile = DIMENSIONS
+            .iter()
+            .map(|x| (*x, usage.get(x).checked_div(capacity.get(x)).unwrap_or_default()))
+            .collect();
+        let (dominant_resource, dominant_share) = DIMENSIONS
+            .iter()
+            .map(|x| (x.to_string(), shares.get(x)))
+            .max_by_key(|(_, share)| *share)
+            .unwrap_or_default();
+        Self { user, tasks, usage, shares, dominant_resource, dominant_share }
+    }
+}
+
+/// Groups usage of running tasks by user, returns allocations sorted by dominant share
+pub fn allocations<'a>(
+    running: impl IntoIterator<Item = (&'a String, NormalizedResourceProfile)>,
+    capacity: &NormalizedResourceProfile,
+) -> Vec<Allocation> {
+    let mut usage: HashMap<&String, (usize, NormalizedResourceProfile)> = HashMap::new();
+    for (user, profile) in running {
+        let entry = usage.entry(user).or_default();
+        entry.0 += 1;
+        entry.1 += profile;
+    }
+    let mut allocations: Vec<Allocation> = usage
+        .into_iter()
+        .map(|(user, (tasks, usage))| Allocation::new(user.clone(), tasks, usage, capacity))
+        .collect();
+    allocations.sort_by(|a, b| (a.dominant_share, &a.user).cmp(&(b.dominant_share, &b.user)));
+    allocations
+}
+
+/// Cost of using `share` of the cluster above the `allowed` share, both as fractions of the
+/// cluster. Shares within the allowed one pay nothing, the cost grows up to `max_cost`.
+///
+/// Namespaces are allowed their fair share. Following DRF, users are allowed the smallest
+/// dominant share so the least served user pays nothing and is served first.
+pub fn excess_cost(share: Decimal, allowed: Decimal, max_cost: Decimal) -> Decimal {
+    let excess = (share - allowed).max(Decimal::new(0, 0)).min(Decimal::new(1, 0));
+    excess * max_cost
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dominant_share() {
+        let capacity: NormalizedResourceProfile =
+            DIMENSIONS.iter().map(|x| (*x, Decimal::new(2, 0))).collect();
+        let (alice, bob) = ("alice".to_string(), "bob".to_string());
+        let cpu: NormalizedResourceProfile =
+            vec![(dimension::IPC, Decimal::new(4, 1)), (dimension::MEMORY, Decimal::new(1, 1))]
+                .into_iter()
+                .collect();
+        let memory: NormalizedResourceProfile =
+            vec![(dimension::MEMORY, Decimal::new(2, 1))].into_iter().collect();
+        let running = vec![(&alice, cpu.clone()), (&alice, cpu), (&bob, memory)];
+
+        let allocations = allocations(running, &capacity);
+        assert_eq!(allocations[0].user, bob);
+        assert_eq!(allocations[0].dominant_resource, dimension::MEMORY);
+        assert_eq!(allocations[0].dominant_share, Decimal::new(1, 1));
+        assert_eq!(allocations[1].user, alice);
+        assert_eq!(allocations[1].tasks, 2);
+        assert_eq!(allocations[1].dominant_resource, dimension::IPC);
+        assert_eq!(allocations[1].dominant_share, Decimal::new(4, 1));
+
+        let max = Decimal::new(6, 0);
+        assert_eq!(excess_cost(Decimal::new(1, 1), Decimal::new(1, 1), max), Decimal::new(0, 0));
+        assert_eq!(excess_cost(Decimal::new(4, 1), Decimal::new(1, 1), max), Decimal::new(18, 1));
+    }
+
+    #[test]
+    fn excess_cost_is_capped() {
+        let max = Decimal::new(6, 0);
+        assert_eq!(excess_cost(Decimal::new(2, 1), Decimal::new(5, 1), max), Decimal::new(0, 0));
+        assert_eq!(excess_cost(Decimal::new(75, 2), Decimal::new(5, 1), max), Decimal::new(15, 1));
+        assert_eq!(excess_cost(Decimal::new(3, 0), Decimal::new(0, 0), max), max);
+    }
+}
@@ -0,0 +1,47 @@
+use crate::prelude::*;
+
+/// Rejects placement of gangs that could not be placed as a whole.
+///
+/// `gangs` maps gang name to all schedulable members. Members of a rejected gang which were
+/// not running in `old` schedule are removed from `new`, so none of them is started.
+///
+/// Returns names of rejected gangs
+pub fn enforce_gangs<K>(
+    gangs: &HashMap<String, Vec<K>>,
+    old: &HashMap<K, Uuid>,
+    new: &mut HashMap<K, Uuid>,
+) -> Vec<String>
+where
+    K: Eq + std::hash::Hash,
+{
+    let mut rejected = vec![];
+    for (gang, members) in gangs {
+        if members.iter().all(|x| new.contains_key(x)) {
+            continue;
+        }
+        for member in members {
+            if !old.contains_key(member) {
+                new.remove(member);
+            }
+        }
+        rejected.push(gang.clone());
+    }
+    rejected
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn partial_gang_is_not_started() {
+        let server = Uuid::new_v4();
+        let gangs: HashMap<_, _> =
+            vec![("a".to_string(), vec![1, 2]), ("b".to_string(), vec![3])].into_iter().collect();
+        let mut new: HashMap<_, _> = vec![(1, server), (3, server)].into_iter().collect();
+        let rejected = enforce_gangs(&gangs, &HashMap::new(), &mut new);
+        assert_eq!(rejected, vec!["a".to_string()]);
+        assert_eq!(new.len(), 1);
+        assert!(new.contains_key(&3));
+    }
+}
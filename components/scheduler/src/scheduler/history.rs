@@ -0,0 +1,80 @@
+use super::{ResourceProfile, Snapshot};
+use crate::prelude::*;
+use std::path::PathBuf;
+
+/// Peak task profiles observed on each server, persisted across scheduler restarts.
+///
+/// Benchmark doesn't always reach maximum performance of a server, tasks can exceed it
+pub struct PeakHistory {
+    path: PathBuf,
+    peaks: HashMap<Uuid, ResourceProfile>,
+    /// Peak raised since the last snapshot
+    changed: bool,
+}
+
+impl PeakHistory {
+    /// Loads history from `path`, missing or corrupted file starts empty history
+    pub fn load(path: PathBuf) -> Self {
+        let peaks = std::fs::read(&path)
+            .ok()
+            .and_then(|x| match serde_json::from_slice(&x) {
+                Ok(peaks) => Some(peaks),
+                Err(e) => {
+                    error!("Can't read server peak history '{}': {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self { path, peaks, changed: false }
+    }
+
+    pub fn get(&self, server: &Uuid) -> Option<&ResourceProfile> {
+        self.peaks.get(server)
+    }
+
+    /// Records task profile observed on `server`, returns true if peak was raised
+    pub fn observe(&mut self, server: Uuid, profile: &ResourceProfile) -> bool {
+        let peak = self.peaks.entry(server).or_default();
+        let raised = peak.max_by_resource(profile);
+        if raised == *peak {
+            return false;
+        }
+        *peak = raised;
+        self.changed = true;
+        true
+    }
+
+    /// Peaks to be persisted, none if no peak was raised since the last snapshot
+    pub fn snapshot(&mut self) -> Option<Snapshot> {
+        if !std::mem::replace(&mut self.changed, false) {
+            return None;
+        }
+        Snapshot::new(&self.path, &self.peaks)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::dimension::{DISK, IPC, MEMORY};
+    use super::*;
+
+    #[test]
+    fn observe_raises_peak_per_resource() {
+        let mut history = PeakHistory::load(PathBuf::from("/nonexistent/peaks.json"));
+        let server = Uuid::new_v4();
+        let profile: ResourceProfile =
+            vec![(IPC, Decimal::new(2, 0)), (MEMORY, Decimal::new(10, 0)), (DISK, Decimal::new(5, 0))]
+                .into_iter()
+                .collect();
+        assert!(history.observe(server, &profile));
+        assert!(!history.observe(server, &profile));
+
+        let profile: ResourceProfile =
+            vec![(IPC, Decimal::new(1, 0)), (MEMORY, Decimal::new(20, 0))].into_iter().collect();
+        assert!(history.observe(server, &profile));
+        let peak = history.get(&server).unwrap();
+        assert_eq!(peak.get(IPC), Decimal::new(2, 0));
+        assert_eq!(peak.get(MEMORY), Decimal::new(20, 0));
+        assert_eq!(peak.get(DISK), Decimal::new(5, 0));
+    }
+}
@@ -0,0 +1,104 @@
+use super::Snapshot;
+use crate::prelude::*;
+use std::path::PathBuf;
+
+/// Smoothing factor of slowdown moving average
+const ALPHA: Decimal = Decimal::from_parts(2, 0, 0, false, 1);
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    /// Highest IPC observed for task signature, approximates IPC of the task running alone
+    baseline: HashMap<String, Decimal>,
+    /// Victim -> aggressor -> relative IPC drop of victim caused by aggressor
+    slowdown: HashMap<String, HashMap<String, Decimal>>,
+}
+
+/// Learns how much tasks slow each other down when sharing a server.
+///
+/// Tasks are identified by signature so the knowledge is reused by new instances of the same
+/// image. Matrix is persisted across scheduler restarts.
+pub struct InterferenceMatrix {
+    path: PathBuf,
+    state: State,
+    /// Observed since the last snapshot
+    changed: bool,
+}
+
+impl InterferenceMatrix {
+    /// Loads matrix from `path`, missing or corrupted file starts empty matrix
+    pub fn load(path: PathBuf) -> Self {
+        let state = std::fs::read(&path)
+            .ok()
+            .and_then(|x| match serde_json::from_slice(&x) {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    error!("Can't read interference matrix '{}': {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        Self { path, state, changed: false }
+    }
+
+    /// Records IPC of `task` measured while running next to `colocated` tasks. IPC drop against
+    /// baseline is split evenly between co-located tasks.
+    pub fn observe(&mut self, task: &str, ipc: Decimal, colocated: &[&str]) {
+        self.changed = true;
+        let baseline = self.state.baseline.entry(task.to_string()).or_default();
+        *baseline = std::cmp::max(*baseline, ipc);
+        if colocated.is_empty() || baseline.is_zero() {
+            return;
+        }
+        let drop = (Decimal::new(1, 0) - ipc / *baseline) / Decimal::from(colocated.len() as u64);
+        let row = self.state.slowdown.entry(task.to_string()).or_default();
+        for aggressor in colocated {
+            let value = row.entry((*aggressor).to_string()).or_default();
+            *value = *value * (Decimal::new(1, 0) - ALPHA) + drop * ALPHA;
+        }
+    }
+
+    /// Relative IPC drop of `victim` caused by `aggressor`
+    pub fn slowdown(&self, victim: &str, aggressor: &str) -> Decimal {
+        self.state
+            .slowdown
+            .get(victim)
+            .and_then(|x| x.get(aggressor))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Expected slowdown of `task` and tasks it would share server with, summed for all pairs
+    pub fn penalty<'a>(&self, task: &str, colocated: impl IntoIterator<Item = &'a str>) -> Decimal {
+        colocated.into_iter().map(|x| self.slowdown(task, x) + self.slowdown(x, task)).sum()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!(self.state.slowdown)
+    }
+
+    /// State to be persisted, none if nothing was observed since the last snapshot
+    pub fn snapshot(&mut self) -> Option<Snapshot> {
+        if !std::mem::replace(&mut self.changed, false) {
+            return None;
+        }
+        Snapshot::new(&self.path, &self.state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn learns_slowdown_from_colocated_tasks() {
+        let mut matrix = InterferenceMatrix::load(PathBuf::from("/nonexistent/matrix.json"));
+        matrix.observe("a", Decimal::new(2, 0), &[]);
+        matrix.observe("a", Decimal::new(1, 0), &["b", "c"]);
+
+        // ipc halved, 0.25 per co-located task smoothed by ALPHA
+        assert_eq!(matrix.slowdown("a", "b"), Decimal::new(5, 2));
+        assert_eq!(matrix.slowdown("a", "c"), Decimal::new(5, 2));
+        assert_eq!(matrix.slowdown("b", "a"), Decimal::new(0, 0));
+        assert_eq!(matrix.penalty("b", vec!["a"]), Decimal::new(5, 2));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+
+/// Whether server accepts tasks
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub enum ServerState {
+    Active,
+    /// No new tasks are placed on the server, running tasks stay
+    Cordoned,
+    /// Running tasks are moved to other servers as migration limits allow
+    Draining,
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        Self::Active
+    }
+}
+
+impl FromStr for ServerState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(Self::Active),
+            "cordoned" => Ok(Self::Cordoned),
+            "draining" => Ok(Self::Draining),
+            _ => Err(format!("unknown server state '{}'", s)),
+        }
+    }
+}
+
+/// Planned maintenance, server is drained for the whole window
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct MaintenanceWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl MaintenanceWindow {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.start <= now && now < self.end
+    }
+}
+
+/// State of server at `now`, an active maintenance window drains the server
+pub fn effective_state(
+    state: ServerState,
+    maintenance: &[MaintenanceWindow],
+    now: DateTime<Utc>,
+) -> ServerState {
+    if maintenance.iter().any(|x| x.is_active(now)) {
+        ServerState::Draining
+    } else {
+        state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn maintenance_window_drains_server() {
+        let now = Utc::now();
+        let windows = vec![
+            MaintenanceWindow { start: now - Duration::hours(2), end: now - Duration::hours(1) },
+            MaintenanceWindow { start: now + Duration::hours(1), end: now + Duration::hours(2) },
+        ];
+        assert_eq!(effective_state(ServerState::Cordoned, &windows, now), ServerState::Cordoned);
+        let later = now + Duration::minutes(90);
+        assert_eq!(effective_state(ServerState::Active, &windows, later), ServerState::Draining);
+    }
+}
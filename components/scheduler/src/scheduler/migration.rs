@@ -0,0 +1,98 @@
+use crate::prelude::*;
+use getset::{CopyGetters, Setters};
+use std::time::Duration;
+
+/// Controls how eagerly already running tasks are moved between servers
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, CopyGetters, Setters)]
+pub struct MigrationPolicy {
+    /// Extra cost (in scaled flow graph units) of placing a running task on a different server
+    #[getset(get_copy = "pub", set = "pub")]
+    cost: i64,
+    /// Task is pinned to its server until it has been running there for at least this long
+    #[getset(get_copy = "pub", set = "pub")]
+    min_residency: Duration,
+    /// Maximum number of tasks moved in a single scheduling round
+    #[getset(get_copy = "pub", set = "pub")]
+    max_per_round: usize,
+}
+
+impl MigrationPolicy {
+    pub fn new(cost: i64, min_residency: Duration, max_per_round: usize) -> Self {
+        Self { cost, min_residency, max_per_round }
+    }
+}
+
+impl Default for MigrationPolicy {
+    fn default() -> Self {
+        Self { cost: 100, min_residency: Duration::from_secs(60), max_per_round: 10 }
+    }
+}
+
+/// Reverts moves from `new` schedule above `max` back to their `old` server. Moves postponed
+/// in more past rounds, counted by `waiting`, go first.
+/// New placements and descheduled tasks are not counted as migrations.
+///
+/// Returns tasks whose migrations were reverted
+pub fn limit_migrations<K, V>(
+    old: &HashMap<K, V>,
+    new: &mut HashMap<K, V>,
+    max: usize,
+    waiting: &HashMap<K, usize>,
+) -> Vec<K>
+where
+    K: Eq + std::hash::Hash + Clone + Ord,
+    V: Eq + Clone,
+{
+    let mut moved: Vec<K> = new
+        .iter()
+        .filter(|(k, v)| old.get(k).map_or(false, |x| x != *v))
+        .map(|(k, _)| k.clone())
+        .collect();
+    // Every postponed move waits longer than the ones going first, so it eventually happens
+    // even if other tasks want to move every round
+    moved.sort_by(|a, b| {
+        let rounds = |k: &K| waiting.get(k).copied().unwrap_or_default();
+        rounds(b).cmp(&rounds(a)).then_with(|| a.cmp(b))
+    });
+    let reverted: Vec<K> = moved.into_iter().skip(max).collect();
+    for k in &reverted {
+        new.insert(k.clone(), old[k].clone());
+    }
+    reverted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn limit_migrations_reverts_excess_moves() {
+        let old: HashMap<u32, u32> = vec![(1, 10), (2, 10), (3, 10)].into_iter().collect();
+        let mut new: HashMap<u32, u32> =
+            vec![(1, 20), (2, 20), (3, 10), (4, 20)].into_iter().collect();
+        assert_eq!(limit_migrations(&old, &mut new, 1, &HashMap::new()), vec![2]);
+        assert_eq!(new[&1], 20);
+        assert_eq!(new[&2], 10);
+        assert_eq!(new[&3], 10);
+        assert_eq!(new[&4], 20);
+    }
+
+    #[test]
+    fn postponed_migrations_eventually_happen() {
+        // Every task wants to move to the other server each round
+        let mut current: HashMap<u32, u32> =
+            vec![(1, 10), (2, 10), (3, 10), (4, 10)].into_iter().collect();
+        let mut waiting = HashMap::new();
+        let mut migrated = HashSet::new();
+        for _ in 0..4 {
+            let mut new: HashMap<u32, u32> = current.iter().map(|(k, v)| (*k, 30 - v)).collect();
+            let reverted = limit_migrations(&current, &mut new, 1, &waiting);
+            migrated.extend(new.iter().filter(|(k, v)| current[k] != **v).map(|(k, _)| *k));
+            let rounds = |x: &u32| waiting.get(x).copied().unwrap_or_default() + 1;
+            waiting = reverted.into_iter().map(|x| (x, rounds(&x))).collect();
+            current = new;
+        }
+        assert_eq!(migrated.len(), 4);
+    }
+}
@@ -1,20 +1,63 @@
+mod aggregation;
+mod audit;
+mod capacity;
+mod catalog;
+mod clock;
+mod cron;
+mod fairness;
+mod gang;
+mod history;
+mod interference;
+mod maintenance;
+mod migration;
+mod namespace;
+mod placement;
+mod plan;
+mod profiling;
+mod queue;
 mod resource_profile;
 #[allow(clippy::module_inception)]
 mod scheduler;
 mod server;
+mod snapshot;
+mod solver;
 mod task;
 mod virtual_resource;
+mod weights;
+mod workflow;
+mod workload;
 
+pub use self::aggregation::{Aggregation, AggregationPolicy};
+pub use self::audit::{AuditQuery, Decision, EdgeCost, RoundEvent, TaskDecision};
+pub use self::capacity::{AdmissionError, Resources};
+pub use self::catalog::WorkloadClass;
+pub use self::clock::{Clock, ManualClock, SystemClock};
+pub use self::cron::{ConcurrencyPolicy, CronExpression, ScheduledTask, Trigger};
+pub use self::fairness::Allocation;
+pub use self::maintenance::{MaintenanceWindow, ServerState};
+pub use self::migration::MigrationPolicy;
+pub use self::namespace::{Namespace, Quota};
+pub use self::placement::{parse_labels, AffinityKind, AffinityRule, Constraints};
+pub use self::plan::{Move, Plan, Scenario};
+pub use self::profiling::ProfilingPolicy;
+pub use self::queue::{QueueEntry, QueuePolicy};
+pub use self::resource_profile::dimension;
 pub use self::resource_profile::NormalizedResourceProfile;
 pub use self::resource_profile::ResourceProfile;
 pub type NormalizedTask = Task<NormalizedResourceProfile>;
 pub type NormalizedServer = Server<NormalizedResourceProfile>;
 pub use self::scheduler::Scheduler;
 pub use self::server::Server;
+pub use self::snapshot::Snapshot;
+pub use self::solver::Solver;
 pub use self::task::State;
 pub use self::task::Task;
 pub use self::task::TaskCommand;
 pub use self::virtual_resource::VirtualResource;
+pub use self::weights::{WeightConfig, Weights};
+pub use self::workflow::{Dag, FailurePolicy, TaskStatus};
+pub use self::workload::Workload;
+pub use self::workload::WorkloadKind;
 use cost_flow::Graphable;
 
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
@@ -0,0 +1,83 @@
+use super::{AdmissionError, Resources};
+use crate::prelude::*;
+
+/// Namespace tasks belong to unless specified
+pub const DEFAULT: &str = "default";
+
+/// Limits of a namespace, unlimited if not set
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Quota {
+    /// Summed absolute requests of running and waiting tasks
+    pub requests: Option<Resources>,
+    /// Number of running and waiting tasks
+    pub tasks: Option<usize>,
+}
+
+/// Project owning tasks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Namespace {
+    pub name: String,
+    #[serde(default)]
+    pub quota: Quota,
+    /// Fair-share weight, namespace is entitled to `weight / sum of weights` of the cluster
+    #[serde(default = "default_weight")]
+    pub weight: Decimal,
+}
+
+fn default_weight() -> Decimal {
+    Decimal::new(1, 0)
+}
+
+impl Namespace {
+    pub fn new(name: String) -> Self {
+        Self { name, quota: Quota::default(), weight: default_weight() }
+    }
+
+    /// Checks that namespace with `tasks` active tasks summing to `requested` can take
+    /// another task with `requests`
+    pub fn admit(
+        &self,
+        tasks: usize,
+        requested: Resources,
+        requests: Option<Resources>,
+    ) -> Result<(), AdmissionError> {
+        if let Some(max) = self.quota.tasks {
+            if tasks >= max {
+                return Err(AdmissionError(format!(
+                    "Namespace '{}' reached its quota of {} tasks",
+                    self.name, max
+                )));
+            }
+        }
+        if let Some(quota) = self.quota.requests {
+            let requested = requested + requests.unwrap_or_default();
+            let exceeded = requested.saturating_sub(&quota);
+            let shortfall = Resources::default().shortfall(&exceeded);
+            if !shortfall.is_empty() {
+                return Err(AdmissionError(format!(
+                    "Namespace '{}' exceeds its quota of {}",
+                    self.name,
+                    shortfall.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quota() {
+        let mut namespace = Namespace::new("team".to_string());
+        namespace.quota.tasks = Some(2);
+        namespace.quota.requests = Some(Resources { cpu_millis: 1000, ..Default::default() });
+        let requests = Some(Resources { cpu_millis: 400, ..Default::default() });
+
+        assert!(namespace.admit(1, Resources { cpu_millis: 600, ..Default::default() }, requests).is_ok());
+        assert!(namespace.admit(1, Resources { cpu_millis: 700, ..Default::default() }, requests).is_err());
+        assert!(namespace.admit(2, Resources::default(), None).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use super::Server;
+use super::Task;
+use crate::prelude::*;
+use std::collections::BTreeMap;
+
+pub type Labels = BTreeMap<String, String>;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub enum AffinityKind {
+    /// Task is placed only on servers running a task matching the selector
+    Affinity,
+    /// Task is never placed on servers running a task matching the selector
+    AntiAffinity,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub struct AffinityRule {
+    pub kind: AffinityKind,
+    /// Labels of other tasks the rule applies to
+    pub selector: Labels,
+}
+
+/// Restricts servers the task can be placed on
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq, Hash)]
+pub struct Constraints {
+    /// Server has to have all of these labels
+    pub node_selector: Labels,
+    pub affinity: Vec<AffinityRule>,
+}
+
+impl Constraints {
+    pub fn is_empty(&self) -> bool {
+        self.node_selector.is_empty() && self.affinity.is_empty()
+    }
+
+    /// Checks if task can be placed on `server` given tasks already placed on it.
+    ///
+    /// `any_matching` reports if selector matches any placed task in the cluster, affinity
+    /// rule without matching task is ignored so that the first task can be placed.
+    pub fn allows<T, U>(
+        &self,
+        task: &Task<T>,
+        server: &Server<U>,
+        colocated: &[&Task<T>],
+        any_matching: impl Fn(&Labels) -> bool,
+    ) -> bool {
+        if !matches(&self.node_selector, server.labels()) {
+            return false;
+        }
+        self.affinity.iter().all(|rule| {
+            let mut others =
+                colocated.iter().filter(|x| x.id() != task.id()).map(|x| x.labels());
+            match rule.kind {
+                AffinityKind::Affinity => {
+                    others.any(|x| matches(&rule.selector, x)) || !any_matching(&rule.selector)
+                }
+                AffinityKind::AntiAffinity => !others.any(|x| matches(&rule.selector, x)),
+            }
+        })
+    }
+}
+
+/// Every label from `selector` is present in `labels` with the same value
+pub fn matches(selector: &Labels, labels: &Labels) -> bool {
+    selector.iter().all(|(k, v)| labels.get(k) == Some(v))
+}
+
+/// Parses labels in `key=value,key2=value2` format
+pub fn parse_labels(s: &str) -> Labels {
+    s.split(',')
+        .filter_map(|x| {
+            let mut kv = x.splitn(2, '=');
+            let key = kv.next()?.trim();
+            let value = kv.next()?.trim();
+            if key.is_empty() {
+                None
+            } else {
+                Some((key.to_string(), value.to_string()))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn task(labels: &str, constraints: Constraints) -> Task<()> {
+        let mut task = Task::new("t".to_string(), None, "img".to_string(), false, None);
+        task.set_labels(parse_labels(labels)).set_constraints(constraints);
+        task
+    }
+
+    #[test]
+    fn parse() {
+        let labels = parse_labels("disk=ssd, zone=a,invalid,");
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["disk"], "ssd");
+        assert_eq!(labels["zone"], "a");
+    }
+
+    #[test]
+    fn node_selector_and_anti_affinity() {
+        let mut server = Server::<()>::new(Uuid::new_v4(), "a".to_string(), None);
+        server.set_labels(parse_labels("disk=ssd"));
+        let constraints = Constraints {
+            node_selector: parse_labels("disk=ssd"),
+            affinity: vec![AffinityRule {
+                kind: AffinityKind::AntiAffinity,
+                selector: parse_labels("app=db"),
+            }],
+        };
+        let db = task("app=db", constraints.clone());
+        let other = task("app=db", Default::default());
+        assert!(constraints.allows(&db, &server, &[&db], |_| true));
+        assert!(!constraints.allows(&db, &server, &[&other], |_| true));
+
+        server.set_labels(parse_labels("disk=hdd"));
+        assert!(!constraints.allows(&db, &server, &[], |_| true));
+    }
+}
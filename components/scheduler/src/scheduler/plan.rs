@@ -0,0 +1,74 @@
+use super::{ResourceProfile, Task, WeightConfig};
+use crate::prelude::*;
+
+/// Hypothetical changes a plan is computed for
+#[derive(Default)]
+pub struct Scenario {
+    /// Tasks added to the cluster
+    pub tasks: Vec<Task<ResourceProfile>>,
+    /// Servers all tasks are removed from
+    pub drain: Vec<Uuid>,
+    /// Weights replacing the configured ones
+    pub weights: Option<WeightConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Move {
+    pub task: Uuid,
+    pub from: Uuid,
+    pub to: Uuid,
+}
+
+/// Result of a scheduling round, computed without touching the current schedule
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Plan {
+    /// Task -> server after the round
+    pub schedule: HashMap<Uuid, Uuid>,
+    /// Total cost of the flow
+    pub cost: u64,
+    /// Tasks that were not running before
+    pub placed: Vec<Uuid>,
+    pub moved: Vec<Move>,
+    /// Tasks that stop running
+    pub descheduled: Vec<Uuid>,
+    /// Tasks kept on their server as the round moved enough tasks
+    pub postponed: Vec<Uuid>,
+}
+
+impl Plan {
+    /// Plan changing `old` schedule to `schedule`
+    pub fn new(old: &HashMap<Uuid, Uuid>, schedule: HashMap<Uuid, Uuid>, cost: u64) -> Self {
+        let mut placed: Vec<Uuid> =
+            schedule.keys().filter(|x| !old.contains_key(x)).copied().collect();
+        let mut moved: Vec<Move> = schedule
+            .iter()
+            .filter_map(|(task, to)| match old.get(task) {
+                Some(from) if from != to => Some(Move { task: *task, from: *from, to: *to }),
+                _ => None,
+            })
+            .collect();
+        let mut descheduled: Vec<Uuid> =
+            old.keys().filter(|x| !schedule.contains_key(x)).copied().collect();
+        placed.sort();
+        moved.sort_by_key(|x| x.task);
+        descheduled.sort();
+        Self { schedule, cost, placed, moved, descheduled, postponed: vec![] }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff_against_old_schedule() {
+        let (a, b, c, d) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let (s1, s2) = (Uuid::new_v4(), Uuid::new_v4());
+        let old = vec![(a, s1), (b, s1), (c, s1)].into_iter().collect();
+        let new = vec![(a, s1), (b, s2), (d, s2)].into_iter().collect();
+        let plan = Plan::new(&old, new, 10);
+        assert_eq!(plan.placed, vec![d]);
+        assert_eq!(plan.moved, vec![Move { task: b, from: s1, to: s2 }]);
+        assert_eq!(plan.descheduled, vec![c]);
+    }
+}
@@ -0,0 +1,92 @@
+use super::placement::{self, Labels};
+use super::{NormalizedResourceProfile, NormalizedServer, Weights};
+use crate::prelude::*;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Controls isolation of tasks whose profile is unknown
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProfilingPolicy {
+    pub enabled: bool,
+    /// Labels of servers dedicated to profiling, the least loaded server is used if none matches
+    pub selector: Labels,
+    /// Minimal time task is profiled before it can join normal placement
+    pub window: Duration,
+    /// Number of newest samples that have to be stable
+    pub min_samples: usize,
+    /// Maximal spread of a normalized resource in the newest samples of a stable profile
+    pub max_spread: Decimal,
+}
+
+impl Default for ProfilingPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            selector: vec![("role".to_string(), "profiling".to_string())].into_iter().collect(),
+            window: Duration::from_secs(300),
+            min_samples: 5,
+            max_spread: Decimal::new(1, 1),
+        }
+    }
+}
+
+impl ProfilingPolicy {
+    /// Samples are stable if each resource of the newest `min_samples` samples stays within
+    /// `max_spread`
+    pub fn is_stable(&self, samples: &[NormalizedResourceProfile]) -> bool {
+        if self.min_samples == 0 || samples.len() < self.min_samples {
+            return false;
+        }
+        let newest = &samples[samples.len() - self.min_samples..];
+        let dimensions: HashSet<&str> = newest.iter().flat_map(|x| x.dimensions().map(|(k, _)| k)).collect();
+        dimensions.into_iter().all(|dimension| {
+            let values = newest.iter().map(|x| x.get(dimension));
+            let max = values.clone().max().unwrap_or_default();
+            let min = values.min().unwrap_or_default();
+            max - min <= self.max_spread
+        })
+    }
+
+    /// Servers tasks are profiled on and whether they are dedicated to profiling. Dedicated
+    /// servers are those matching the selector, otherwise the least loaded benchmarked server is
+    /// shared with other tasks.
+    pub fn servers(
+        &self,
+        servers: &HashMap<Uuid, NormalizedServer>,
+        usage: &HashMap<Uuid, NormalizedResourceProfile>,
+        weights: &Weights,
+    ) -> (HashSet<Uuid>, bool) {
+        let labeled: HashSet<Uuid> = servers
+            .values()
+            .filter(|x| !self.selector.is_empty() && placement::matches(&self.selector, x.labels()))
+            .map(|x| *x.id())
+            .collect();
+        if !labeled.is_empty() {
+            return (labeled, true);
+        }
+        let least_loaded = servers
+            .values()
+            .filter(|x| x.profile().is_some())
+            .min_by_key(|x| {
+                (usage.get(x.id()).map_or_else(Decimal::default, |x| x.inner_product(weights)), *x.id())
+            })
+            .map(|x| *x.id());
+        (least_loaded.into_iter().collect(), false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stable_profile() {
+        let policy = ProfilingPolicy { min_samples: 3, ..Default::default() };
+        let samples = |values: &[i64]| -> Vec<NormalizedResourceProfile> {
+            values.iter().map(|x| vec![("ipc", Decimal::new(*x, 2))].into_iter().collect()).collect()
+        };
+        assert!(!policy.is_stable(&samples(&[50, 50])));
+        assert!(policy.is_stable(&samples(&[10, 50, 55, 45])));
+        assert!(!policy.is_stable(&samples(&[50, 55, 70])));
+    }
+}
@@ -0,0 +1,120 @@
+use crate::prelude::*;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Task waiting to be placed
+#[derive(Clone, Debug, Serialize)]
+pub struct QueueEntry {
+    pub task: Uuid,
+    pub enqueued_at: DateTime<Utc>,
+    pub deadline: Option<DateTime<Utc>>,
+    /// Multiplier of the cost of leaving the task unscheduled
+    pub priority: Decimal,
+    /// Time a running task is expected to free resources for the task, none if unknown
+    pub estimated_start: Option<DateTime<Utc>>,
+}
+
+/// Controls how the cost of leaving a task unscheduled rises while it waits
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuePolicy {
+    /// Waiting time adding the base cost once more
+    pub aging: Duration,
+    /// Time before deadline from which the cost rises up to the maximum
+    pub deadline_window: Duration,
+    /// Maximal multiplier of the base cost
+    pub max_priority: Decimal,
+}
+
+impl Default for QueuePolicy {
+    fn default() -> Self {
+        Self {
+            aging: Duration::from_secs(600),
+            deadline_window: Duration::from_secs(600),
+            max_priority: Decimal::new(4, 0),
+        }
+    }
+}
+
+impl QueuePolicy {
+    /// Multiplier of unscheduled cost of task waiting since `enqueued_at` with `deadline`
+    pub fn priority(
+        &self,
+        enqueued_at: DateTime<Utc>,
+        deadline: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Decimal {
+        let one = Decimal::new(1, 0);
+        let seconds = |x: chrono::Duration| Decimal::from(x.num_seconds().max(0));
+        let ratio = |a: Decimal, b: Duration| a.checked_div(Decimal::from(b.as_secs())).unwrap_or_default();
+
+        let aging = one + ratio(seconds(now - enqueued_at), self.aging);
+        let urgency = deadline.map_or(one, |deadline| {
+            let remaining = ratio(seconds(deadline - now), self.deadline_window).min(one);
+            one + (self.max_priority - one) * (one - remaining)
+        });
+        aging.max(urgency).min(self.max_priority).max(one)
+    }
+}
+
+/// Mean runtime of finished tasks by task signature
+#[derive(Clone, Debug, Default)]
+pub struct Runtimes(HashMap<String, (Duration, u32)>);
+
+impl Runtimes {
+    pub fn observe(&mut self, signature: String, runtime: Duration) {
+        let (mean, count) = self.0.entry(signature).or_insert((Duration::default(), 0));
+        *count += 1;
+        let total = *mean * (*count - 1) + runtime;
+        *mean = total / *count;
+    }
+
+    pub fn expected(&self, signature: &str) -> Option<Duration> {
+        self.0.get(signature).map(|x| x.0)
+    }
+}
+
+/// Assigns start estimates to `queue` ordered by priority. Every expected finish of a running
+/// task frees resources for one queued task, finishes already overdue are expected `now`.
+pub fn estimate_starts(queue: &mut [QueueEntry], mut finishes: Vec<DateTime<Utc>>, now: DateTime<Utc>) {
+    finishes.sort();
+    let starts = finishes.into_iter().map(|x| Some(x.max(now))).chain(std::iter::repeat(None));
+    for (entry, start) in queue.iter_mut().zip(starts) {
+        entry.estimated_start = start;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn priority_rises_with_waiting_and_deadline() {
+        let policy = QueuePolicy::default();
+        let now = Utc.ymd(2020, 5, 1).and_hms(12, 0, 0);
+        let minutes = chrono::Duration::minutes;
+        assert_eq!(policy.priority(now, None, now), Decimal::new(1, 0));
+        assert_eq!(policy.priority(now - minutes(15), None, now), Decimal::new(25, 1));
+        assert_eq!(policy.priority(now - minutes(60), None, now), Decimal::new(4, 0));
+        assert_eq!(policy.priority(now, Some(now + minutes(5)), now), Decimal::new(25, 1));
+        assert_eq!(policy.priority(now, Some(now - minutes(1)), now), Decimal::new(4, 0));
+    }
+
+    #[test]
+    fn estimated_start() {
+        let now = Utc.ymd(2020, 5, 1).and_hms(12, 0, 0);
+        let entry = |task| QueueEntry {
+            task,
+            enqueued_at: now,
+            deadline: None,
+            priority: Decimal::new(1, 0),
+            estimated_start: None,
+        };
+        let mut queue = vec![entry(Uuid::new_v4()), entry(Uuid::new_v4()), entry(Uuid::new_v4())];
+        let later = now + chrono::Duration::minutes(5);
+        estimate_starts(&mut queue, vec![later, now - chrono::Duration::minutes(1)], now);
+        assert_eq!(queue[0].estimated_start, Some(now));
+        assert_eq!(queue[1].estimated_start, Some(later));
+        assert_eq!(queue[2].estimated_start, None);
+    }
+}
@@ -1,93 +1,146 @@
+use super::Weights;
 use crate::prelude::*;
-use derive_more::{Add, AddAssign, Div, Mul, MulAssign, Sub, SubAssign};
 use std::cmp::Ordering;
-
-#[derive(
-    Default, Copy, Clone, PartialEq, Hash, Eq, Debug, Serialize, Add, AddAssign, Sub, SubAssign,
-)]
-pub struct ResourceProfile {
-    pub ipc: Decimal,
-    pub memory: u64,
-    pub network: u64,
-    pub disk: u64,
+use std::collections::BTreeMap;
+
+/// Names of resource dimensions collected by profiler
+pub mod dimension {
+    pub const IPC: &str = "ipc";
+    pub const MEMORY: &str = "memory";
+    pub const NETWORK: &str = "network";
+    pub const DISK: &str = "disk";
+    pub const CACHE_MISSES: &str = "cache_misses";
+    pub const CACHE_REFERENCES: &str = "cache_references";
+    pub const L1_DCACHE_LOADS: &str = "l1_dcache_loads";
+    pub const L1_DCACHE_LOAD_MISSES: &str = "l1_dcache_load_misses";
+    pub const L1_ICACHE_LOAD_MISSES: &str = "l1_icache_load_misses";
+    pub const LLC_LOADS: &str = "llc_loads";
+    pub const LLC_LOAD_MISSES: &str = "llc_load_misses";
+    pub const CYCLES: &str = "cycles";
+    /// LLC misses per thousand cycles, approximates memory bandwidth and cache pressure
+    pub const CACHE_PRESSURE: &str = "cache_pressure";
 }
 
-impl ResourceProfile {
-    pub const ONE: ResourceProfile = Self { ipc: one(), memory: 1, network: 1, disk: 1 };
+/// Resource usage as named dimensions, missing dimension has value 0
+#[derive(Default, Clone, PartialEq, Hash, Eq, Debug, Serialize, Deserialize)]
+pub struct ResourceProfile(BTreeMap<String, Decimal>);
+
+/// Resource profile relative to the most performant server, the max profile being all 1
+#[derive(Default, Clone, PartialEq, Hash, Eq, Debug, Serialize)]
+pub struct NormalizedResourceProfile(BTreeMap<String, Decimal>);
+
+/// Implements operations shared by both profile kinds, dimensions missing in one of
+/// the operands are treated as 0
+macro_rules! dimension_ops {
+    ($profile:ident) => {
+        impl $profile {
+            pub fn get(&self, dimension: &str) -> Decimal {
+                self.0.get(dimension).copied().unwrap_or_default()
+            }
+
+            pub fn set(&mut self, dimension: &str, value: Decimal) -> &mut Self {
+                self.0.insert(dimension.to_string(), value);
+                self
+            }
+
+            /// Resource values by dimension name
+            pub fn dimensions(&self) -> impl Iterator<Item = (&str, Decimal)> {
+                self.0.iter().map(|(k, v)| (&k[..], *v))
+            }
+
+            fn merge(&self, other: &Self, f: impl Fn(Decimal, Decimal) -> Decimal) -> Self {
+                let keys: std::collections::BTreeSet<_> =
+                    self.0.keys().chain(other.0.keys()).collect();
+                Self(keys.into_iter().map(|k| (k.clone(), f(self.get(k), other.get(k)))).collect())
+            }
+        }
 
-    pub fn two() -> ResourceProfile {
-        Self::ONE + Self::ONE
-    }
+        impl<S: Into<String>> std::iter::FromIterator<(S, Decimal)> for $profile {
+            fn from_iter<I: IntoIterator<Item = (S, Decimal)>>(iter: I) -> Self {
+                Self(iter.into_iter().map(|(k, v)| (k.into(), v)).collect())
+            }
+        }
 
-    pub fn normalize(&self, other: &ResourceProfile) -> NormalizedResourceProfile {
-        NormalizedResourceProfile {
-            ipc: self.ipc.normalize_to(&other.ipc),
-            memory: self.memory.normalize_to(&other.memory),
-            network: self.network.normalize_to(&other.network),
-            disk: self.disk.normalize_to(&other.disk),
+        impl std::ops::Add for $profile {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                self.merge(&rhs, |a, b| a + b)
+            }
         }
-    }
+
+        impl std::ops::AddAssign for $profile {
+            fn add_assign(&mut self, rhs: Self) {
+                *self = self.merge(&rhs, |a, b| a + b);
+            }
+        }
+
+        impl std::ops::Sub for $profile {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                self.merge(&rhs, |a, b| a - b)
+            }
+        }
+
+        impl std::ops::Mul<Decimal> for $profile {
+            type Output = Self;
+
+            fn mul(mut self, rhs: Decimal) -> Self {
+                self.0.values_mut().for_each(|x| *x *= rhs);
+                self
+            }
+        }
+
+        impl std::ops::Div<Decimal> for $profile {
+            type Output = Self;
+
+            fn div(mut self, rhs: Decimal) -> Self {
+                self.0.values_mut().for_each(|x| *x /= rhs);
+                self
+            }
+        }
+    };
 }
 
-impl std::ops::Div for ResourceProfile {
-    type Output = Self;
+dimension_ops!(ResourceProfile);
+dimension_ops!(NormalizedResourceProfile);
 
-    fn div(mut self, rhs: Self) -> Self::Output {
-        self.ipc /= rhs.ipc;
-        self.memory /= rhs.memory;
-        self.network /= rhs.network;
-        self.disk /= rhs.disk;
-        self
+impl ResourceProfile {
+    /// Maximum of each resource
+    pub fn max_by_resource(&self, other: &ResourceProfile) -> ResourceProfile {
+        self.merge(other, std::cmp::max)
     }
-}
 
-#[derive(
-    Default,
-    Clone,
-    PartialEq,
-    Hash,
-    Eq,
-    Debug,
-    Serialize,
-    Add,
-    Sub,
-    AddAssign,
-    SubAssign,
-    Div,
-    Mul,
-    MulAssign,
-)]
-pub struct NormalizedResourceProfile {
-    ipc: Decimal,
-    memory: Decimal,
-    network: Decimal,
-    disk: Decimal,
+    pub fn normalize(&self, other: &ResourceProfile) -> NormalizedResourceProfile {
+        self.dimensions().map(|(k, v)| (k, v.normalize_to(&other.get(k)))).collect()
+    }
 }
 
 const fn one() -> Decimal {
     Decimal::from_parts(1, 0, 0, false, 0)
 }
 
-const fn two() -> Decimal {
-    Decimal::from_parts(2, 0, 0, false, 0)
-}
-
 impl NormalizedResourceProfile {
-    pub const MAX: NormalizedResourceProfile =
-        NormalizedResourceProfile { ipc: one(), disk: one(), memory: one(), network: one() };
+    /// Profile of the most performant server
+    pub fn max<'a>(dimensions: impl IntoIterator<Item = &'a str>) -> NormalizedResourceProfile {
+        dimensions.into_iter().map(|x| (x, one())).collect()
+    }
 
-    pub fn inner_product(&self) -> Decimal {
-        self.ipc  * two() + self.memory * two() + self.network + self.disk
+    /// Weighted sum of all resources
+    pub fn inner_product(&self, weights: &Weights) -> Decimal {
+        self.dimensions().map(|(name, value)| value * weights.get(name)).sum()
     }
 
     pub fn has_negative_resource(&self) -> bool {
-        self.ipc.is_sign_negative() || self.memory.is_sign_negative() || self.network.is_sign_negative() || self.disk.is_sign_negative()
+        self.0.values().any(Decimal::is_sign_negative)
     }
 }
 
 impl Ord for NormalizedResourceProfile {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.inner_product().cmp(&other.inner_product())
+        let weights = Weights::default();
+        self.inner_product(&weights).cmp(&other.inner_product(&weights))
     }
 }
 
@@ -104,6 +157,38 @@ mod test {
     fn one() {
         assert_eq!(one(), Decimal::new(1, 0));
     }
+
+    #[test]
+    fn weighted_inner_product() {
+        let max = NormalizedResourceProfile::max(vec!["ipc", "memory", "network", "disk"]);
+        assert_eq!(max.inner_product(&Weights::default()), Decimal::new(6, 0));
+        let weights = Weights::new(
+            vec![("disk".to_string(), Decimal::new(5, 0)), ("unknown".to_string(), one())]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(max.inner_product(&weights), Decimal::new(5, 0));
+    }
+
+    #[test]
+    fn operations_across_dimensions() {
+        let server: ResourceProfile =
+            vec![(dimension::IPC, Decimal::new(2, 0)), (dimension::CACHE_PRESSURE, Decimal::new(4, 0))]
+                .into_iter()
+                .collect();
+        let task: ResourceProfile =
+            vec![(dimension::IPC, Decimal::new(1, 0)), (dimension::DISK, Decimal::new(3, 0))]
+                .into_iter()
+                .collect();
+
+        let max = server.max_by_resource(&task);
+        assert_eq!(max.get(dimension::DISK), Decimal::new(3, 0));
+
+        let free = server.normalize(&max) - task.normalize(&max);
+        assert_eq!(free.get(dimension::IPC), Decimal::new(5, 1));
+        assert_eq!(free.get(dimension::CACHE_PRESSURE), one());
+        assert!(free.has_negative_resource());
+    }
 }
 
 trait DecimalNormalize {
@@ -112,17 +197,9 @@ trait DecimalNormalize {
 
 impl DecimalNormalize for Decimal {
     fn normalize_to(&self, other: &Decimal) -> Decimal {
-        // TODO: task can have higher profile than the server it running one
-        // Server profile should be updated if task has better performance than benchmark
-        // debug_assert!(self < other);
-        self / other
-    }
-}
-
-impl DecimalNormalize for u64 {
-    fn normalize_to(&self, other: &u64) -> Decimal {
-        let num: Decimal = (*self).into();
-        let other: Decimal = (*other).into();
-        num.normalize_to(&other)
+        // Server profiles are raised by observed task peaks, so measured task profile doesn't
+        // exceed the maximum profile it is normalized to. Requests are user provided and can.
+        // Dimension no server reported is ignored.
+        self.checked_div(*other).unwrap_or_default()
     }
 }
@@ -1,66 +1,649 @@
+use super::audit::{AuditLog, RoundEvent, Solution};
+use super::catalog::ProfileCatalog;
+use super::clock::{Clock, SystemClock};
+use super::cron::{ConcurrencyPolicy, Run, ScheduledTask};
+use super::fairness::{self, Allocation};
+use super::gang;
+use super::AggregationPolicy;
+use std::collections::{HashSet, VecDeque};
+use super::history::PeakHistory;
+use super::interference::InterferenceMatrix;
+use super::migration::{self, MigrationPolicy};
+use super::namespace::Namespace;
+use super::placement;
+use super::plan::{Plan, Scenario};
+use super::profiling::ProfilingPolicy;
+use super::queue::{self, QueueEntry, QueuePolicy, Runtimes};
 use super::Node;
 use super::NormalizedResourceProfile;
 use super::NormalizedServer;
 use super::NormalizedTask;
 use super::ResourceProfile;
 use super::Server;
+use super::ServerState;
+use super::Snapshot;
+use super::Solver;
 use super::Task;
 use super::TaskCommand;
+use super::dimension;
+use super::AdmissionError;
+use super::Resources;
 use super::VirtualResource;
+use super::WeightConfig;
+use super::Workload;
+use super::workflow::{Dag, TaskStatus, Workflow};
 use crate::prelude::*;
 use cost_flow::{Capacity, Cost};
 use futures::channel::mpsc;
 use futures_util::sink::SinkExt;
+use telemetry::{Carrier, TraceSpan};
 use tokio::sync::watch;
 use rust_decimal::prelude::ToPrimitive;
+use std::time::{Duration, Instant};
 
 type ServerTaskSubscription = mpsc::Sender<TaskCommand>;
 type ServerID = Uuid;
 type TaskID = Uuid;
+type WorkloadID = Uuid;
+
+/// Tasks and their placement, written by the leader and restored by the replica taking over
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ClusterState {
+    servers: Vec<Server<ResourceProfile>>,
+    tasks: Vec<Task<ResourceProfile>>,
+    schedule: HashMap<TaskID, ServerID>,
+    placed_at: HashMap<TaskID, chrono::DateTime<chrono::Utc>>,
+    started_at: HashMap<TaskID, chrono::DateTime<chrono::Utc>>,
+    queue: HashMap<TaskID, chrono::DateTime<chrono::Utc>>,
+    removing: HashMap<ServerID, HashSet<TaskID>>,
+    profiled: HashSet<TaskID>,
+    workloads: Vec<Workload>,
+    workflows: Vec<Workflow>,
+    scheduled: Vec<ScheduledTask>,
+    namespaces: Vec<Namespace>,
+}
+
 pub struct Scheduler {
     tasks: HashMap<TaskID, Task<ResourceProfile>>,
+    // Jobs and services expanded into tasks
+    workloads: HashMap<WorkloadID, Workload>,
     servers: HashMap<ServerID, Server<ResourceProfile>>,
     // Channel to agent running on server
     server_subscriptions: HashMap<ServerID, ServerTaskSubscription>,
+    // Commands for servers whose agents aren't subscribed, sent once they subscribe
+    undelivered: HashMap<ServerID, VecDeque<TaskCommand>>,
     schedule: HashMap<TaskID, ServerID>,
-    // Channel for updating web ui
-    notif_channel: (watch::Sender<String>, watch::Receiver<String>),
+    // Time when task was placed on its current server
+    placed_at: HashMap<TaskID, chrono::DateTime<chrono::Utc>>,
+    // Time when task was first placed, kept when it's moved
+    started_at: HashMap<TaskID, chrono::DateTime<chrono::Utc>>,
+    // Tasks removed from servers whose agents didn't confirm stopping them yet
+    removing: HashMap<ServerID, HashSet<TaskID>>,
+    // Trace context of placements the agents didn't accept yet
+    traces: HashMap<TaskID, Carrier>,
+    // Time since when unscheduled tasks wait for placement
+    queue: HashMap<TaskID, chrono::DateTime<chrono::Utc>>,
+    // Raises cost of leaving waiting tasks unscheduled
+    queue_policy: QueuePolicy,
+    // Runtimes of finished tasks used to estimate when queued tasks start
+    runtimes: Runtimes,
+    migration_policy: MigrationPolicy,
+    // Number of consecutive rounds migrations of tasks were postponed
+    postponed: HashMap<TaskID, usize>,
+    // Algorithm solving the flow graph
+    solver: Solver,
+    // Combines profile samples of a task
+    aggregation: AggregationPolicy,
+    // Isolation of tasks with unknown profile
+    profiling: ProfilingPolicy,
+    // Tasks that finished profiling and joined normal placement
+    profiled: HashSet<TaskID>,
+    // Resources reserved for system on newly registered servers
+    default_reserved: Resources,
+    // Observed task peaks raising server profiles
+    history: PeakHistory,
+    // Resource weights used to compute costs
+    weights: WeightConfig,
+    // Learned slowdown between co-located tasks
+    interference: InterferenceMatrix,
+    // Past profiles by task signature
+    catalog: ProfileCatalog,
+    // Namespace configuration, namespaces without one use defaults
+    namespaces: HashMap<String, Namespace>,
+    // DAGs of tasks with dependencies
+    workflows: HashMap<Uuid, Workflow>,
+    // Tasks started at times given by cron expressions or timestamps
+    scheduled: HashMap<Uuid, ScheduledTask>,
+    // Decisions of past rounds
+    audit: AuditLog,
+    // File the cluster state is persisted to
+    state_path: std::path::PathBuf,
+    // Time read by policies, virtual in simulation
+    clock: Arc<dyn Clock>,
+    // Channel for updating web ui with the latest round
+    notif_channel: (watch::Sender<Option<RoundEvent>>, watch::Receiver<Option<RoundEvent>>),
 }
 
 impl Scheduler {
-    pub fn new() -> Self {
+    /// Persistent state is stored in `data_dir`
+    pub fn new(data_dir: &Path) -> Self {
         Self {
-            notif_channel: watch::channel(String::new()),
+            history: PeakHistory::load(data_dir.join("server_peaks.json")),
+            interference: InterferenceMatrix::load(data_dir.join("interference.json")),
+            catalog: ProfileCatalog::load(data_dir.join("catalog.json")),
+            namespaces: Default::default(),
+            workflows: Default::default(),
+            scheduled: Default::default(),
+            weights: WeightConfig::load(&data_dir.join("weights.json")).unwrap_or_else(|e| {
+                error!("Can't load resource weights, using defaults: {}", e);
+                WeightConfig::default()
+            }),
+            audit: AuditLog::new(data_dir.join("audit.jsonl")),
+            state_path: data_dir.join("cluster.json"),
+            notif_channel: watch::channel(None),
+            clock: Arc::new(SystemClock),
             tasks: Default::default(),
+            workloads: Default::default(),
             servers: Default::default(),
             schedule: Default::default(),
+            placed_at: Default::default(),
+            started_at: Default::default(),
+            removing: Default::default(),
+            traces: Default::default(),
+            queue: Default::default(),
+            queue_policy: Default::default(),
+            runtimes: Default::default(),
+            migration_policy: Default::default(),
+            postponed: Default::default(),
+            solver: Default::default(),
+            aggregation: Default::default(),
+            profiling: Default::default(),
+            profiled: Default::default(),
+            default_reserved: Resources {
+                cpu_millis: 500,
+                memory: 512 * 1024 * 1024,
+                disk: 0,
+                network: 0,
+            },
             server_subscriptions: Default::default(),
+            undelivered: Default::default(),
         }
     }
 
+    /// Reloads learned and cluster state persisted in `data_dir`, used by replica taking over
+    /// leadership
+    pub fn restore(&mut self, data_dir: &Path) {
+        self.state_path = data_dir.join("cluster.json");
+        match std::fs::read(&self.state_path) {
+            Ok(x) => match serde_json::from_slice(&x) {
+                Ok(state) => self.restore_cluster(state),
+                Err(e) => error!("Can't parse '{}': {}", self.state_path.display(), e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error!("Can't read '{}': {}", self.state_path.display(), e),
+        }
+        self.history = PeakHistory::load(data_dir.join("server_peaks.json"));
+        self.interference = InterferenceMatrix::load(data_dir.join("interference.json"));
+        self.catalog = ProfileCatalog::load(data_dir.join("catalog.json"));
+        let weights = data_dir.join("weights.json");
+        if weights.exists() {
+            match WeightConfig::load(&weights) {
+                Ok(weights) => self.weights = weights,
+                Err(e) => error!("Can't load resource weights, keeping current: {}", e),
+            }
+        }
+        for server in self.servers.values_mut() {
+            server.set_peak(self.history.get(server.id()).cloned());
+        }
+    }
+
+    pub fn migration_policy(&self) -> &MigrationPolicy {
+        &self.migration_policy
+    }
+
+    pub fn set_migration_policy(&mut self, policy: MigrationPolicy) {
+        self.migration_policy = policy;
+    }
+
+    pub fn queue_policy(&self) -> &QueuePolicy {
+        &self.queue_policy
+    }
+
+    pub fn set_queue_policy(&mut self, policy: QueuePolicy) {
+        self.queue_policy = policy;
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Current time of the scheduler clock
+    pub fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.now()
+    }
+
+    /// Time task spent on its current server
+    fn placed_for(&self, id: &TaskID) -> Option<Duration> {
+        let placed_at = self.placed_at.get(id)?;
+        Some((self.now() - *placed_at).to_std().unwrap_or_default())
+    }
+
+    /// Time task runs since it was placed, including time spent on servers it was moved from
+    fn running_for(&self, id: &TaskID) -> Option<Duration> {
+        let started_at = self.started_at.get(id)?;
+        Some((self.now() - *started_at).to_std().unwrap_or_default())
+    }
+
+    pub fn solver(&self) -> Solver {
+        self.solver
+    }
+
+    pub fn set_solver(&mut self, solver: Solver) {
+        self.solver = solver;
+    }
+
+    pub fn aggregation(&self) -> &AggregationPolicy {
+        &self.aggregation
+    }
+
+    pub fn set_aggregation(&mut self, policy: AggregationPolicy) {
+        self.aggregation = policy;
+    }
+
+    pub fn profiling(&self) -> &ProfilingPolicy {
+        &self.profiling
+    }
+
+    pub async fn set_profiling(&mut self, policy: ProfilingPolicy) {
+        self.profiling = policy;
+        self.schedule().await;
+    }
+
+    pub fn weights(&self) -> &WeightConfig {
+        &self.weights
+    }
+
+    pub async fn set_weights(&mut self, weights: WeightConfig) {
+        self.weights = weights;
+        self.schedule().await;
+    }
+
     /// Check if task was scheduler before, if so and it's finished running make it schedulable
-    /// else create a new task.
+    /// else create a new task. Accepts a single task or a DAG of tasks, task of a DAG becomes
+    /// schedulable once all its dependencies succeed.
     /// 
-    /// Schedulability property is based on the task name
-    pub async fn insert_task(&mut self, task: Task<ResourceProfile>) {
-        if let Some(task) = self.tasks.values_mut().find(|x| x.name() == task.name()) {
-            task.set_schedulable(true);
-        } else {
-            self.tasks.insert(*task.id(), task);
+    /// Schedulability property is based on the task name. Task which can't fit on any
+    /// server is rejected, task which doesn't fit now stays unscheduled until resources free up.
+    /// DAG is admitted only as a whole.
+    pub async fn insert_task(&mut self, dag: impl Into<Dag>) -> Result<(), AdmissionError> {
+        self.insert_task_traced(dag, &Carrier::new()).await
+    }
+
+    /// Inserts tasks as [`insert_task`](Self::insert_task) in a span continuing trace `parent`,
+    /// the round placing the tasks continues the same trace
+    pub async fn insert_task_traced(
+        &mut self,
+        dag: impl Into<Dag>,
+        parent: &Carrier,
+    ) -> Result<(), AdmissionError> {
+        let dag = dag.into();
+        let span = TraceSpan::start("insert_task", parent);
+        span.set_attribute("tasks", dag.tasks.len() as u64);
+        dag.validate()?;
+        for (i, task) in dag.tasks.iter().enumerate() {
+            self.admit(task, &dag.tasks[..i])?;
+        }
+        let mut ids = HashMap::new();
+        for task in &dag.tasks {
+            let id = if let Some(existing) = self.tasks.values_mut().find(|x| *x == task) {
+                existing.set_schedulable(true).set_exit_code(None).set_volume(dag.volume.clone());
+                *existing.id()
+            } else {
+                let mut task = task.clone();
+                task.set_volume(dag.volume.clone());
+                // Image profiled before classifies the task, otherwise the declared class is kept
+                if let Some(class) = self.catalog.classify(&task.signature(), &self.max_profile()) {
+                    task.set_workload_class(Some(class));
+                }
+                let id = *task.id();
+                self.tasks.insert(id, task);
+                id
+            };
+            // Task running again is no longer cancelled by previous runs of its workflows
+            self.workflows.values_mut().for_each(|x| {
+                x.cancelled.remove(&id);
+            });
+            ids.insert(task.name().clone(), id);
+        }
+        if dag.is_workflow() {
+            let workflow = Workflow::new(&dag, &ids);
+            self.workflows.insert(workflow.id, workflow);
+        }
+        self.schedule_traced(&span.carrier()).await;
+        Ok(())
+    }
+
+    /// Marks task finished with `exit_code`. Failed task cancels tasks of its workflows according
+    /// to their failure policy.
+    pub async fn finish_task(&mut self, id: &TaskID, exit_code: i64) {
+        self.traces.remove(id);
+        let runtime = self.running_for(id);
+        if let Some(task) = self.tasks.get_mut(id) {
+            task.set_schedulable(false).set_exit_code(Some(exit_code));
+            if let Some(runtime) = runtime {
+                self.runtimes.observe(task.signature(), runtime);
+            }
+        }
+        for workflow in self.workflows.values_mut() {
+            for id in workflow.to_cancel(&self.tasks) {
+                debug!("Cancelling task '{}' of failed workflow '{}'", self.tasks[&id].name(), workflow.name);
+                self.tasks.get_mut(&id).unwrap().set_schedulable(false);
+                workflow.cancelled.insert(id);
+            }
         }
         self.schedule().await;
     }
 
+    /// Task of a workflow waiting for its dependencies
+    fn is_blocked(&self, id: &TaskID) -> bool {
+        !self.schedule.contains_key(id) && self.workflows.values().any(|x| x.is_blocked(id, &self.tasks))
+    }
+
+    /// Workflows with status of each task and graphviz of their progress
+    pub fn get_workflows(&self) -> Vec<serde_json::Value> {
+        let mut workflows: Vec<&Workflow> = self.workflows.values().collect();
+        workflows.sort_by(|a, b| a.name.cmp(&b.name));
+        workflows
+            .into_iter()
+            .map(|workflow| {
+                let tasks: HashMap<String, _> = workflow
+                    .dependencies
+                    .keys()
+                    .filter_map(|id| {
+                        let status = workflow.status(id, &self.tasks, &self.schedule);
+                        Some((self.tasks.get(id)?.name().clone(), status))
+                    })
+                    .collect();
+                serde_json::json!({
+                    "workflow": workflow,
+                    "tasks": tasks,
+                    "graphviz": workflow.graphviz(&self.tasks, &self.schedule),
+                })
+            })
+            .collect()
+    }
+
+    /// Adds or replaces scheduled task with the same name, history of the replaced one is kept
+    pub fn insert_scheduled_task(&mut self, mut scheduled: ScheduledTask) {
+        let existing = self.scheduled.values().find(|x| x.name == scheduled.name).map(|x| x.id);
+        if let Some(previous) = existing.and_then(|id| self.scheduled.remove(&id)) {
+            scheduled.inherit(previous);
+        }
+        self.scheduled.insert(scheduled.id, scheduled);
+    }
+
+    /// Stops scheduling new runs, already started runs keep running
+    pub fn remove_scheduled_task(&mut self, id: &Uuid) {
+        self.scheduled.remove(id);
+    }
+
+    /// Scheduled tasks with status of their past runs
+    pub fn get_scheduled_tasks(&self) -> Vec<serde_json::Value> {
+        let mut scheduled: Vec<&ScheduledTask> = self.scheduled.values().collect();
+        scheduled.sort_by(|a, b| a.name.cmp(&b.name));
+        scheduled
+            .into_iter()
+            .map(|x| {
+                let runs: Vec<_> = x
+                    .history
+                    .iter()
+                    .map(|run| {
+                        let status = run.task.and_then(|id| {
+                            Some(TaskStatus::of(self.tasks.get(&id)?, self.schedule.contains_key(&id)))
+                        });
+                        serde_json::json!({ "run": run, "status": status })
+                    })
+                    .collect();
+                serde_json::json!({ "scheduled": x, "runs": runs })
+            })
+            .collect()
+    }
+
+    /// Starts runs of scheduled tasks that are due
+    pub async fn run_scheduled_tasks(&mut self) {
+        let now = self.now();
+        let due: Vec<Uuid> = self.scheduled.values().filter(|x| x.is_due(now)).map(|x| x.id).collect();
+        for id in due {
+            let mut scheduled = self.scheduled.remove(&id).unwrap();
+            let scheduled_at = scheduled.next_run.unwrap();
+            let active: Vec<TaskID> = scheduled
+                .tasks()
+                .filter(|x| self.tasks.get(x).map_or(false, |x| *x.schedulable()))
+                .collect();
+            let run = if scheduled.concurrency == ConcurrencyPolicy::Forbid && !active.is_empty() {
+                debug!("Skipping run of '{}', previous run is still active", scheduled.name);
+                Run { scheduled_at, task: None, error: Some("Previous run is still active".to_string()) }
+            } else {
+                if scheduled.concurrency == ConcurrencyPolicy::Replace {
+                    for id in &active {
+                        self.tasks.get_mut(id).unwrap().set_schedulable(false);
+                    }
+                }
+                let task = scheduled.instantiate();
+                let task_id = *task.id();
+                match self.insert_task(task).await {
+                    Ok(()) => Run { scheduled_at, task: Some(task_id), error: None },
+                    Err(e) => Run { scheduled_at, task: None, error: Some(e.to_string()) },
+                }
+            };
+            scheduled.record(run, now);
+            self.scheduled.insert(id, scheduled);
+        }
+    }
+
+    /// Checks namespace quota and that task requests fit allocatable resources of at least one server.
+    /// Servers that haven't reported capacity yet are assumed to fit, with no servers
+    /// in cluster task waits for one to register. `admitted` are tasks submitted together with
+    /// `task` that were admitted before it.
+    fn admit(
+        &self,
+        task: &Task<ResourceProfile>,
+        admitted: &[Task<ResourceProfile>],
+    ) -> Result<(), AdmissionError> {
+        // Active task of the same namespace and name absorbs the resubmitted one, the namespace
+        // doesn't get another task
+        let resubmitted = self.tasks.values().any(|x| x == task && *x.schedulable());
+        // Quota counts waiting and running tasks of the namespace. Tasks are told apart by id,
+        // equality of tasks compares their names only
+        let active: Vec<_> = self
+            .tasks
+            .values()
+            .filter(|x| *x.schedulable() && !admitted.iter().any(|y| y.id() == x.id()))
+            .chain(admitted)
+            .filter(|x| x.namespace() == task.namespace() && x.id() != task.id())
+            .collect();
+        let requested =
+            active.iter().fold(Resources::default(), |acc, x| acc + x.requests().unwrap_or_default());
+        if !resubmitted {
+            self.namespace(task.namespace()).admit(active.len(), requested, *task.requests())?;
+        }
+
+        let requests = match task.requests() {
+            Some(requests) => requests,
+            None => return Ok(()),
+        };
+        if self.servers.is_empty()
+            || self.servers.values().any(|x| x.allocatable().map_or(true, |x| x.fits(requests)))
+        {
+            return Ok(());
+        }
+        let reasons: Vec<String> = self
+            .servers
+            .values()
+            .filter_map(|x| {
+                let shortfall = x.allocatable()?.shortfall(requests);
+                Some(format!("{}: not enough {}", x.hostname(), shortfall.join(", ")))
+            })
+            .collect();
+        Err(AdmissionError(format!(
+            "Task '{}' doesn't fit any server ({})",
+            task.name(),
+            reasons.join("; ")
+        )))
+    }
+
+    /// Configured namespace or namespace with defaults
+    fn namespace(&self, name: &str) -> Namespace {
+        self.namespaces.get(name).cloned().unwrap_or_else(|| Namespace::new(name.to_string()))
+    }
+
+    /// Adds or replaces namespace configuration
+    pub async fn insert_namespace(&mut self, namespace: Namespace) {
+        self.namespaces.insert(namespace.name.clone(), namespace);
+        self.schedule().await;
+    }
+
+    /// Namespaces with tasks or configuration, with their active tasks and requests
+    pub fn get_namespaces(&self) -> Vec<serde_json::Value> {
+        let mut names: Vec<&str> = self.tasks.values().map(|x| &x.namespace()[..]).collect();
+        names.extend(self.namespaces.keys().map(|x| &x[..]));
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| {
+                let active: Vec<_> =
+                    self.tasks.values().filter(|x| x.namespace() == name && *x.schedulable()).collect();
+                let requested = active
+                    .iter()
+                    .fold(Resources::default(), |acc, x| acc + x.requests().unwrap_or_default());
+                serde_json::json!({
+                    "namespace": self.namespace(name),
+                    "tasks": active.len(),
+                    "requested": requested,
+                })
+            })
+            .collect()
+    }
+
+    /// Resources allocated to users by the current schedule, least served user first
+    pub fn get_allocations(&self) -> Vec<Allocation> {
+        let (servers, tasks) = self.normalize();
+        self.allocations(&servers, &tasks, &self.schedule)
+    }
+
+    /// Dominant shares of users running tasks in `current` schedule
+    fn allocations(
+        &self,
+        servers: &HashMap<ServerID, NormalizedServer>,
+        tasks: &HashMap<TaskID, NormalizedTask>,
+        current: &HashMap<TaskID, ServerID>,
+    ) -> Vec<Allocation> {
+        let capacity = servers
+            .values()
+            .filter_map(|x| x.profile().clone())
+            .fold(NormalizedResourceProfile::default(), |acc, x| acc + x);
+        let running = current.iter().filter_map(|(task, server)| {
+            let task = &tasks[task];
+            let usage = task
+                .profile(server, &self.aggregation)
+                .or_else(|| task.predicted().clone())
+                .unwrap_or_default();
+            task.user().as_ref().map(|user| (user, usage))
+        });
+        fairness::allocations(running, &capacity)
+    }
+
     pub fn get_tasks(&self) -> Vec<&Task<ResourceProfile>> {
         self.tasks.values().collect()
     }
 
+    /// Number of tasks in each status
+    pub fn task_counts(&self) -> HashMap<TaskStatus, usize> {
+        let mut counts = HashMap::new();
+        for task in self.tasks.values() {
+            let status = match TaskStatus::of(task, self.schedule.contains_key(task.id())) {
+                TaskStatus::Pending if self.is_blocked(task.id()) => TaskStatus::Waiting,
+                status => status,
+            };
+            *counts.entry(status).or_insert(0) += 1;
+        }
+        counts
+    }
+
     pub fn get_task(&mut self, id: &TaskID) -> Option<&mut Task<ResourceProfile>> {
         self.tasks.get_mut(id)
     }
 
-    /// Add or replace server based on `id`
-    pub async fn insert_server(&mut self, server: Server<ResourceProfile>) {
+    /// Add workload or update workload with the same name.
+    ///
+    /// Change of task template triggers rolling update of running replicas
+    pub async fn insert_workload(&mut self, workload: Workload) {
+        if let Some(existing) = self.workloads.values_mut().find(|x| x.name() == workload.name()) {
+            existing.update_template(&workload);
+            existing.set_kind(workload.kind()).set_replicas(workload.replicas());
+            existing.set_max_unavailable(workload.max_unavailable());
+            existing.set_gang(workload.gang());
+        } else {
+            self.workloads.insert(*workload.id(), workload);
+        }
+        self.schedule().await;
+    }
+
+    /// Removes workload and stops all its task instances
+    pub async fn remove_workload(&mut self, id: &WorkloadID) {
+        if self.workloads.remove(id).is_some() {
+            for task in self.tasks.values_mut().filter(|x| x.owner().as_ref() == Some(id)) {
+                task.set_schedulable(false);
+            }
+            self.schedule().await;
+        }
+    }
+
+    pub fn get_workloads(&self) -> Vec<&Workload> {
+        self.workloads.values().collect()
+    }
+
+    /// Expands workloads into task instances, scales them to the desired replica count and
+    /// replaces outdated instances
+    fn reconcile_workloads(&mut self) {
+        let placed: HashSet<TaskID> = self.schedule.keys().copied().collect();
+        let ids: Vec<WorkloadID> = self.workloads.keys().copied().collect();
+        for id in ids {
+            let workload = &self.workloads[&id];
+            let owned: Vec<_> =
+                self.tasks.values().filter(|x| x.owner().as_ref() == Some(&id)).collect();
+            let plan = workload.plan(&owned, &placed);
+            for task in plan.retire {
+                debug!("Retiring task '{}' of '{}'", self.tasks[&task].name(), workload.name());
+                self.tasks.get_mut(&task).unwrap().set_schedulable(false);
+            }
+            for _ in 0..plan.create {
+                let task = self.workloads.get_mut(&id).unwrap().instantiate();
+                // Replicas are admitted like submitted tasks, rejected ones are retried next round
+                if let Err(e) = self.admit(&task, &[]) {
+                    debug!("Replica '{}' not admitted: {}", task.name(), e);
+                    break;
+                }
+                debug!("Creating task '{}' of '{}'", task.name(), self.workloads[&id].name());
+                self.tasks.insert(*task.id(), task);
+            }
+        }
+    }
+
+    /// Add or replace server based on `id`, reserved resources of replaced server are kept
+    pub async fn insert_server(&mut self, mut server: Server<ResourceProfile>) {
+        // Reserved resources, cordon and maintenance survive agent re-registration
+        if let Some(old) = self.servers.get(server.id()) {
+            server
+                .set_reserved(*old.reserved())
+                .set_state(*old.state())
+                .set_maintenance(old.maintenance().clone());
+        } else {
+            server.set_reserved(self.default_reserved);
+        }
+        server.set_peak(self.history.get(server.id()).cloned());
         self.servers.insert(*server.id(), server);
         self.schedule().await;
     }
@@ -73,155 +656,602 @@ impl Scheduler {
         self.servers.get_mut(id)
     }
 
-    pub fn subscribe_server(&mut self, id: Uuid, tx: ServerTaskSubscription) {
+    /// Stores task profile measured on server. Server profile is raised if the task
+    /// exceeded its benchmark.
+    pub fn insert_task_profile(
+        &mut self,
+        task_id: &TaskID,
+        server_id: ServerID,
+        profile: ResourceProfile,
+    ) -> BoxResult<()> {
+        // Learn interference from tasks sharing the server
+        let signature = self.tasks.get(task_id).ok_or("unknown task")?.signature();
+        let colocated: Vec<String> = self
+            .schedule
+            .iter()
+            .filter(|(id, server)| **server == server_id && *id != task_id)
+            .map(|(id, _)| self.tasks[id].signature())
+            .collect();
+        let colocated: Vec<&str> = colocated.iter().map(|x| &x[..]).collect();
+        self.interference.observe(&signature, profile.get(dimension::IPC), &colocated);
+        self.catalog.observe(&signature, &profile);
+        let class = self.catalog.classify(&signature, &self.max_profile());
+
+        let task = self.tasks.get_mut(task_id).ok_or("unknown task")?;
+        task.set_workload_class(class);
+        let raised = self.history.observe(server_id, &profile);
+        task.insert_profile(server_id, profile, self.aggregation.retention);
+        if raised {
+            let peak = self.history.get(&server_id).cloned();
+            if let Some(server) = self.servers.get_mut(&server_id) {
+                debug!("Raising peak profile of server '{}' to {:?}", server.hostname(), peak);
+                server.set_peak(peak);
+            }
+        }
+        Ok(())
+    }
+
+    /// Learned state changed since the last call and the cluster state, written by the caller
+    /// without holding the lock
+    pub fn snapshots(&mut self) -> Vec<Snapshot> {
+        let state = ClusterState {
+            servers: self.servers.values().cloned().collect(),
+            tasks: self.tasks.values().cloned().collect(),
+            schedule: self.schedule.clone(),
+            placed_at: self.placed_at.clone(),
+            started_at: self.started_at.clone(),
+            queue: self.queue.clone(),
+            removing: self.removing.clone(),
+            profiled: self.profiled.clone(),
+            workloads: self.workloads.values().cloned().collect(),
+            workflows: self.workflows.values().cloned().collect(),
+            scheduled: self.scheduled.values().cloned().collect(),
+            namespaces: self.namespaces.values().cloned().collect(),
+        };
+        vec![
+            self.interference.snapshot(),
+            self.catalog.snapshot(),
+            self.history.snapshot(),
+            Snapshot::new(&self.state_path, &state),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Replaces cluster state by the one written by the previous leader, placed tasks keep
+    /// running on their servers
+    fn restore_cluster(&mut self, state: ClusterState) {
+        self.servers = state.servers.into_iter().map(|x| (*x.id(), x)).collect();
+        self.tasks = state.tasks.into_iter().map(|x| (*x.id(), x)).collect();
+        self.schedule = state.schedule;
+        self.workloads = state.workloads.into_iter().map(|x| (*x.id(), x)).collect();
+        self.workflows = state.workflows.into_iter().map(|x| (x.id, x)).collect();
+        self.scheduled = state.scheduled.into_iter().map(|x| (x.id, x)).collect();
+        self.namespaces = state.namespaces.into_iter().map(|x| (x.name.clone(), x)).collect();
+        self.placed_at = state.placed_at;
+        self.started_at = state.started_at;
+        self.queue = state.queue;
+        self.removing = state.removing;
+        self.profiled = state.profiled;
+    }
+
+    /// Learned slowdown, victim -> aggressor -> relative IPC drop
+    pub fn interference(&self) -> serde_json::Value {
+        self.interference.to_json()
+    }
+
+    /// Closes task streams of agents when replica loses leadership, agents reconnect to the new
+    /// leader. State is restored from the data directory if the replica becomes leader again.
+    pub fn step_down(&mut self) {
+        self.server_subscriptions.clear();
+        self.undelivered.clear();
+    }
+
+    /// Subscribes agent of server `id`, commands issued while it wasn't subscribed are sent as
+    /// long as the channel has room, the rest by the next round
+    pub fn subscribe_server(&mut self, id: Uuid, mut tx: ServerTaskSubscription) {
+        if let Some(commands) = self.undelivered.get_mut(&id) {
+            while let Some(cmd) = commands.pop_front() {
+                if let Err(e) = tx.try_send(cmd) {
+                    commands.push_front(e.into_inner());
+                    break;
+                }
+            }
+            if commands.is_empty() {
+                self.undelivered.remove(&id);
+            }
+        }
         self.server_subscriptions.insert(id, tx);
     }
 
+    /// Sends commands not delivered to agent of `server`. Agent whose channel is closed is
+    /// unsubscribed, its commands wait until it subscribes again.
+    async fn deliver(&mut self, server: &ServerID) {
+        let commands = match self.undelivered.get_mut(server) {
+            Some(x) => x,
+            None => return,
+        };
+        if let Some(subscription) = self.server_subscriptions.get_mut(server) {
+            if subscription.is_closed() {
+                debug!("Agent of server '{}' disconnected", server);
+                self.server_subscriptions.remove(server);
+            } else {
+                while let Some(cmd) = commands.pop_front() {
+                    if let Err(e) = subscription.send(cmd).await {
+                        error!("Can't send command to agent of server '{}': {}", server, e);
+                        self.server_subscriptions.remove(server);
+                        break;
+                    }
+                }
+            }
+        }
+        if commands.is_empty() {
+            self.undelivered.remove(server);
+        }
+    }
+
     /// Runs scheduling pipeline
+    /// 0. expands jobs and services into tasks
     /// 1. computes flow graph
     /// 2. creates new schedule
     /// 3. assign tasks to server based on schedule (agent are notified of the change)
     pub async fn schedule(&mut self) {
-        use cost_flow::MinimumCostFlow;
+        self.schedule_traced(&Carrier::new()).await;
+    }
+
+    /// Runs [`schedule`](Self::schedule) in a span continuing trace `parent` of the change that
+    /// triggered the round, new trace is started if it's empty
+    pub async fn schedule_traced(&mut self, parent: &Carrier) {
+        let span = TraceSpan::start("schedule", parent);
+        // Agents that subscribed since the last round get commands issued before
+        let servers: Vec<_> = self.undelivered.keys().copied().collect();
+        for server in servers {
+            self.deliver(&server).await;
+        }
+        self.reconcile_workloads();
         let (servers, tasks) = self.normalize();
-        let mut graph = self.build_flow_graph(&servers, &tasks);
-        graph.minimum_cost_flow();
-        let paths = graph.paths();
-        let _ = self.place_tasks(paths).await;
-        let _ = self.notif_channel.0.broadcast(graph.graphviz());
+        self.finish_profiling(&tasks);
+        let (plan, solution) = self.compute_plan(servers, tasks, &Scenario::default());
+        let rounds = |id: &TaskID| self.postponed.get(id).copied().unwrap_or_default() + 1;
+        self.postponed = plan.postponed.iter().map(|x| (*x, rounds(x))).collect();
+        let names =
+            |id: &TaskID| self.tasks.get(id).map_or_else(|| id.to_string(), |x| x.name().clone());
+        let event = RoundEvent::new(&plan, &self.schedule, solution, names, self.now());
+        if let Err(e) = self.audit.record(&event) {
+            error!("Can't write audit log: {}", e);
+        }
+        crate::metrics::observe_round(&event);
+        span.set_attribute("objective", event.objective);
+        span.set_attribute("nodes", event.nodes as u64);
+        span.set_attribute("edges", event.edges as u64);
+        span.set_attribute("decisions", event.decisions.len() as u64);
+        self.apply_plan(plan, &span.carrier()).await;
+        self.update_queue(self.now());
+        let _ = self.notif_channel.0.broadcast(Some(event));
+    }
+
+    /// Log of past rounds, queried without holding the scheduler lock
+    pub fn audit_log(&self) -> AuditLog {
+        self.audit.clone()
+    }
+
+    /// Tasks left unscheduled join the queue, placed and finished tasks leave it
+    fn update_queue(&mut self, now: chrono::DateTime<chrono::Utc>) {
+        let waiting: HashSet<TaskID> = self
+            .tasks
+            .values()
+            .filter(|x| *x.schedulable() && !self.schedule.contains_key(x.id()) && !self.is_blocked(x.id()))
+            .map(|x| *x.id())
+            .collect();
+        self.queue.retain(|id, _| waiting.contains(id));
+        for id in waiting {
+            self.queue.entry(id).or_insert(now);
+        }
+    }
+
+    /// Multiplier of the cost of leaving `task` unscheduled, rises while task waits in queue
+    fn queue_priority(&self, task: &NormalizedTask, now: chrono::DateTime<chrono::Utc>) -> Decimal {
+        let enqueued_at = self.queue.get(task.id()).copied().unwrap_or(now);
+        self.queue_policy.priority(enqueued_at, *task.deadline(), now)
+    }
+
+    /// Queued tasks by priority with their estimated start, running tasks are expected to
+    /// finish after the mean runtime of their signature
+    pub fn get_queue(&self) -> Vec<QueueEntry> {
+        let now = self.now();
+        let mut queue: Vec<QueueEntry> = self
+            .queue
+            .iter()
+            .filter_map(|(id, enqueued_at)| {
+                let deadline = *self.tasks.get(id)?.deadline();
+                Some(QueueEntry {
+                    task: *id,
+                    enqueued_at: *enqueued_at,
+                    deadline,
+                    priority: self.queue_policy.priority(*enqueued_at, deadline, now),
+                    estimated_start: None,
+                })
+            })
+            .collect();
+        queue.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.enqueued_at.cmp(&b.enqueued_at)));
+        let finishes = self
+            .schedule
+            .keys()
+            .filter_map(|id| {
+                let runtime = self.runtimes.expected(&self.tasks.get(id)?.signature())?;
+                let remaining = runtime.checked_sub(self.running_for(id)?).unwrap_or_default();
+                Some(now + chrono::Duration::from_std(remaining).ok()?)
+            })
+            .collect();
+        queue::estimate_starts(&mut queue, finishes, now);
+        queue
+    }
+
+    /// Computes placement after hypothetical changes of `scenario` without applying it
+    pub fn plan(&self, scenario: &Scenario) -> Plan {
+        let (servers, tasks) = self.normalize();
+        self.compute_plan(servers, tasks, scenario).0
     }
 
     /// Finds maximum profile for all servers and uses the most performant server as a maximum value
-    /// Each resource is normalized to value between (0, 1), the max profile beeing all 1
+    /// Each resource is normalized to value between (0, 1), the max profile beeing all 1.
+    /// Server profile is the benchmark raised by the highest task profiles observed on it.
+    /// Tasks not profiled yet get profile predicted from the catalog.
     fn normalize(&self) -> (HashMap<ServerID, NormalizedServer>, HashMap<TaskID, NormalizedTask>) {
-        use std::cmp::max;
-
         debug!("Normalizing profiles");
-        // TODO: 1. check if tasks have higher profile than it's server and update if so
-        // 2. global maximum profile
-        let max_profile = self
-            .servers
-            .values()
-            .map(|x| x.profile().unwrap_or_else(|| ResourceProfile::ONE))
-            .fold(ResourceProfile::default(), |acc, x| ResourceProfile {
-                ipc: max(acc.ipc, x.ipc),
-                disk: max(acc.disk, x.disk),
-                network: max(acc.network, x.network),
-                memory: max(acc.memory, x.memory),
-            });
+        let max_profile = self.max_profile();
         let servers = self.servers.iter().map(|(k, v)| (*k, v.normalize(&max_profile))).collect();
-        let tasks = self.tasks.iter().map(|(k, v)| (*k, v.normalize(&max_profile))).collect();
+        let tasks = self.tasks.iter().map(|(k, v)| (*k, self.normalize_task(v, &max_profile))).collect();
         (servers, tasks)
     }
 
-    /// Assign task to a server based on result from flow graph
-    async fn place_tasks(&mut self, paths: Vec<cost_flow::Path<Node>>) -> BoxResult<()> {
-        use super::task::State;
+    fn normalize_task(&self, task: &Task<ResourceProfile>, max_profile: &ResourceProfile) -> NormalizedTask {
+        let mut task = task.normalize(max_profile);
+        if task.profiles().is_empty() {
+            let class = *task.workload_class();
+            let predicted = self.catalog.predict(&task.signature(), class, max_profile);
+            task.set_predicted(predicted);
+        }
+        task
+    }
 
-        fn get_server_task(
-            path: cost_flow::Path<Node>,
-        ) -> BoxResult<(NormalizedServer, NormalizedTask)> {
+    /// Tasks without request and prediction are profiled first, profiling finishes when task
+    /// spent the profiling window on its server and its profile there is stable
+    fn finish_profiling(&mut self, tasks: &HashMap<TaskID, NormalizedTask>) {
+        for task in tasks.values().filter(|x| self.is_profiling(x)) {
+            let server_id = match self.schedule.get(task.id()) {
+                Some(id) => id,
+                None => continue,
+            };
+            let elapsed = self.placed_for(task.id()).map_or(false, |x| x >= self.profiling.window);
+            let samples = task.profiles().get(server_id).map_or(&[][..], Vec::as_slice);
+            if elapsed && self.profiling.is_stable(self.aggregation.current_phase(samples)) {
+                debug!("Task '{}' finished profiling", task.name());
+                self.profiled.insert(*task.id());
+            }
+        }
+    }
+
+    fn is_profiling(&self, task: &NormalizedTask) -> bool {
+        self.profiling.enabled
+            && task.request().is_none()
+            && task.predicted().is_none()
+            && !self.profiled.contains(task.id())
+    }
+
+    fn max_profile(&self) -> ResourceProfile {
+        self.servers
+            .values()
+            .map(|x| x.effective_profile().unwrap_or_default())
+            .fold(ResourceProfile::default(), |acc, x| acc.max_by_resource(&x))
+    }
+
+    /// Workload class and mean profile of every task signature seen so far
+    pub fn classes(&self) -> serde_json::Value {
+        self.catalog.to_json(&self.max_profile())
+    }
+
+    /// Solves flow graph for the current state changed by `scenario`. Returns the resulting plan
+    /// and the solved graph.
+    fn compute_plan(
+        &self,
+        mut servers: HashMap<ServerID, NormalizedServer>,
+        mut tasks: HashMap<TaskID, NormalizedTask>,
+        scenario: &Scenario,
+    ) -> (Plan, Solution) {
+        fn get_server_task(path: cost_flow::Path<Node>) -> Option<(ServerID, TaskID)> {
             let mut server = None;
-            let mut tasks = None;
+            let mut task = None;
             for edge in path.edges {
                 match edge.source {
-                    cost_flow::Node::Node(Node::Server(s)) => server = Some(s.clone()),
-                    cost_flow::Node::Node(Node::Task(s)) => tasks = Some(s.clone()),
+                    cost_flow::Node::Node(Node::Server(s)) => server = Some(*s.id()),
+                    cost_flow::Node::Node(Node::Task(s)) => task = Some(*s.id()),
                     _ => {}
                 }
             }
-            Ok((server.ok_or("no server")?, tasks.ok_or("no task")?))
+            Some((server?, task?))
         }
 
+        // 1. Apply hypothetical changes
+        let max_profile = self.max_profile();
+        for task in &scenario.tasks {
+            tasks.insert(*task.id(), self.normalize_task(task, &max_profile));
+        }
+        servers.retain(|id, _| !scenario.drain.contains(id));
+        // Workflow tasks waiting for their dependencies are not placed
+        tasks.retain(|id, _| !self.is_blocked(id));
+        let current: HashMap<TaskID, ServerID> = self
+            .schedule
+            .iter()
+            .filter(|(_, server)| servers.contains_key(server))
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        let weights = scenario.weights.as_ref().unwrap_or(&self.weights);
+        let now = self.now();
+        let states: HashMap<ServerID, ServerState> =
+            servers.values().map(|x| (*x.id(), x.effective_state(now))).collect();
+
+        // 2. Solve flow graph
+        let mut graph = self.build_flow_graph(&servers, &tasks, &current, &states, weights);
+        let started = Instant::now();
+        self.solver.solve(&mut graph);
+        let solution = Solution::new(&graph, started.elapsed());
+        let cost = graph
+            .all_edges()
+            .iter()
+            .map(|x| x.flow.saturating_mul(x.cost))
+            .fold(0, u64::saturating_add);
+
+        // 3. Assign tasks to servers, tasks on paths without server stay unscheduled
         debug!("Assign tasks to servers from graph");
-        let mut normalized_tasks = HashMap::new();
-
-        // 1. Start with empty schedule
-        let old = self.schedule.clone();
-        self.schedule = HashMap::new();
-        for path in paths {
-            let (server, task) = get_server_task(path)?;
-            // 2. Assign task to a server
-            self.schedule.insert(*task.id(), *server.id());
-            normalized_tasks.insert(task.id().clone(), task);
-        }
-        // 3. Get tasks that didn't run before or have been moved to different server
-        let mut to_schedule = self.schedule.clone();
-        to_schedule.retain(|k,v| !(old.get(k).is_some() && old[k] == *v));
-
-        for (task_id, server_id) in to_schedule {
+        let mut schedule = HashMap::new();
+        for path in graph.paths() {
+            if let Some((server, task)) = get_server_task(path) {
+                schedule.insert(task, server);
+            }
+        }
+
+        // 3.1 Tasks of draining servers are only moved, tasks without other placement keep
+        // running until there is one
+        for (task, server) in &current {
+            if states[server] == ServerState::Draining && !schedule.contains_key(task) {
+                schedule.insert(*task, *server);
+            }
+        }
+
+        // 3.2 Keep tasks on their old server if too many tasks would be moved this round
+        let mut postponed = migration::limit_migrations(
+            &current,
+            &mut schedule,
+            self.migration_policy.max_per_round(),
+            &self.postponed,
+        );
+        if !postponed.is_empty() {
+            debug!("Postponed {} migrations to next round", postponed.len());
+        }
+
+        // 3.3 Check constraints between tasks placed in this round
+        Self::enforce_constraints(&servers, &tasks, &current, &mut schedule);
+
+        // 3.4 Start gang members only if the whole gang has been placed
+        let mut gangs: HashMap<String, Vec<TaskID>> = HashMap::new();
+        for task in tasks.values().filter(|x| *x.schedulable()) {
+            if let Some(gang) = task.gang() {
+                gangs.entry(gang.clone()).or_default().push(*task.id());
+            }
+        }
+        for gang in gang::enforce_gangs(&gangs, &current, &mut schedule) {
+            debug!("Gang '{}' could not be placed as a whole", gang);
+        }
+
+        let mut plan = Plan::new(&self.schedule, schedule, cost);
+        postponed.sort();
+        plan.postponed = postponed;
+        (plan, solution)
+    }
+
+    /// Agent of `server` stopped container of the removed `task`
+    pub fn confirm_removal(&mut self, server: &ServerID, task: &TaskID) {
+        if let Some(tasks) = self.removing.get_mut(server) {
+            tasks.remove(task);
+            if tasks.is_empty() {
+                self.removing.remove(server);
+            }
+        }
+    }
+
+    /// Trace context of the latest placement of task `id`, empty once it was accepted
+    pub fn accept_task(&mut self, id: &TaskID) -> Carrier {
+        self.traces.remove(id).unwrap_or_default()
+    }
+
+    /// Draining server no task is placed on and whose agent stopped all removed containers
+    pub fn is_drained(&self, server: &Server<ResourceProfile>, now: chrono::DateTime<chrono::Utc>) -> bool {
+        server.effective_state(now) == ServerState::Draining
+            && !self.schedule.values().any(|x| x == server.id())
+            && !self.removing.contains_key(server.id())
+    }
+
+    /// Replaces schedule with the plan and notifies agents of the changes, commands are traced
+    /// as part of the `round`
+    async fn apply_plan(&mut self, plan: Plan, round: &Carrier) {
+        use super::task::State;
+
+        let old = std::mem::replace(&mut self.schedule, plan.schedule);
+
+        // 1. Start tasks that didn't run before or have been moved to different server
+        let started = plan.placed.iter().map(|x| (*x, self.schedule[x]));
+        let started: Vec<_> = started.chain(plan.moved.iter().map(|x| (x.task, x.to))).collect();
+        for (task_id, server_id) in started {
+            let now = self.now();
+            self.placed_at.insert(task_id, now);
+            self.started_at.entry(task_id).or_insert(now);
             let task = self.tasks[&task_id].clone();
             debug!("Scheduling task '{}' on server '{}'", task.name(), self.servers[&server_id].hostname());
-            self.schedule_task(&server_id, task, State::Run).await;
+            self.schedule_task(&server_id, task, State::Run, round).await;
         }
 
-        // 4. Get descheduled tasks or previous task allocation that has been moved
-        let mut to_deschedule = old.clone();
-        to_deschedule.retain(|k,v| self.schedule.get(k).is_none() || self.schedule[k] != *v);
-
-        for (task_id, server_id) in to_deschedule {
+        // 2. Stop descheduled tasks and previous allocation of moved tasks
+        let mut stopped: Vec<_> = plan.moved.iter().map(|x| (x.task, x.from)).collect();
+        for task_id in plan.descheduled {
+            self.placed_at.remove(&task_id);
+            self.started_at.remove(&task_id);
+            stopped.push((task_id, old[&task_id]));
+        }
+        for (task_id, server_id) in stopped {
             let task = self.tasks[&task_id].clone();
             debug!("Descheduling task '{}' from server '{}'", task.name(), server_id);
-            self.schedule_task(&server_id, task, State::Remove).await;
+            self.removing.entry(server_id).or_default().insert(task_id);
+            self.schedule_task(&server_id, task, State::Remove, round).await;
         }
-
-        Ok(())
     }
 
-    async fn schedule_task(&mut self, server: &ServerID, task: super::Task<super::ResourceProfile>, state: super::task::State) {
-        let subscription = self.server_subscriptions.get_mut(server).unwrap();
+    /// Sends command to the agent of `server` in a span of the `round`. Agent continues the trace
+    /// of placement once it accepts the task.
+    async fn schedule_task(&mut self, server: &ServerID, task: super::Task<super::ResourceProfile>, state: super::task::State, round: &Carrier) {
+        let name = if state == super::task::State::Run { "place_task" } else { "deschedule_task" };
+        let span = TraceSpan::start(name, round);
+        span.set_attribute("task", task.name().clone());
+        span.set_attribute("server", self.servers[server].hostname().clone());
+        if state == super::task::State::Run {
+            self.traces.insert(*task.id(), span.carrier());
+        } else {
+            self.traces.remove(task.id());
+        }
         let cmd = TaskCommand { task, state };
-        subscription.send(cmd).await.unwrap();
+        self.undelivered.entry(*server).or_default().push_back(cmd);
+        self.deliver(server).await;
     }
 
-    pub fn subscribe(&self) -> watch::Receiver<String> {
+    pub fn subscribe(&self) -> watch::Receiver<Option<RoundEvent>> {
         self.notif_channel.1.clone()
     }
 
     fn build_flow_graph(
         &self,
         servers: &HashMap<ServerID,NormalizedServer>,
-        tasks: &HashMap<TaskID, NormalizedTask>
+        tasks: &HashMap<TaskID, NormalizedTask>,
+        current: &HashMap<TaskID, ServerID>,
+        states: &HashMap<ServerID, ServerState>,
+        weight_config: &WeightConfig,
     ) -> cost_flow::Graph<Node> {
         debug!("Building graph");
         let mut graph = cost_flow::Graph::new();
         let cluster =
             graph.add_node(Node::VirtualResource(VirtualResource::new("Cluster".to_string())));
         let task_count = tasks.len();
+        let weights = &weight_config.cluster;
+        let max_cost = NormalizedResourceProfile::max(weights.dimensions()).inner_product(weights);
+        let now = self.now();
     
         // 1. Get current server utilization
         let mut server_usage = HashMap::new();
-        for (key, value) in &self.schedule {
+        let mut colocated: HashMap<ServerID, Vec<&NormalizedTask>> = HashMap::new();
+        let mut requested: HashMap<ServerID, Resources> = HashMap::new();
+        let mut namespace_usage: HashMap<&String, Decimal> = HashMap::new();
+        for (key, value) in current {
+            let usage = tasks[key]
+                .profile(value, &self.aggregation)
+                .or_else(|| tasks[key].predicted().clone())
+                .unwrap_or_default();
+            *namespace_usage.entry(tasks[key].namespace()).or_default() += usage.inner_product(weights);
             let val = server_usage.entry(*value).or_insert_with(Default::default);
-            *val += tasks[key].profile(value).unwrap_or(NormalizedResourceProfile::default());
+            *val += usage;
+            colocated.entry(*value).or_default().push(&tasks[key]);
+            *requested.entry(*value).or_default() += tasks[key].requests().unwrap_or_default();
+        }
+
+        // 1.1 Share of cluster used by namespaces and their fair shares, dominant shares of users
+        let cluster_capacity: Decimal =
+            servers.values().filter_map(|x| x.profile().as_ref()).map(|x| x.inner_product(weights)).sum();
+        let mut namespace_weights: HashMap<&String, Decimal> = HashMap::new();
+        for task in tasks.values().filter(|x| *x.schedulable()) {
+            namespace_weights.entry(task.namespace()).or_insert_with(|| self.namespace(task.namespace()).weight);
         }
+        let total_weight: Decimal = namespace_weights.values().sum();
+        let fair_share_cost = |namespace: &String| {
+            let share = namespace_usage.get(namespace).copied().unwrap_or_default()
+                .checked_div(cluster_capacity)
+                .unwrap_or_default();
+            let fair_share = namespace_weights[namespace].checked_div(total_weight).unwrap_or_default();
+            fairness::excess_cost(share, fair_share, max_cost).scaled_i64()
+        };
+        let dominant_shares: HashMap<String, Decimal> = self
+            .allocations(servers, tasks, current)
+            .into_iter()
+            .map(|x| (x.user, x.dominant_share))
+            .collect();
+        // Users without running tasks have share 0 and are served first
+        let has_idle_user = tasks
+            .values()
+            .filter(|x| *x.schedulable())
+            .filter_map(|x| x.user().as_ref())
+            .any(|x| !dominant_shares.contains_key(x));
+        let min_share = if has_idle_user {
+            Decimal::default()
+        } else {
+            dominant_shares.values().min().copied().unwrap_or_default()
+        };
+        let drf_cost = |user: &Option<String>| {
+            let share = user.as_ref().and_then(|x| dominant_shares.get(x)).copied().unwrap_or_default();
+            fairness::excess_cost(share, min_share, max_cost).scaled_i64()
+        };
 
-        let mut server_to_node = HashMap::new();
+        // 1.2 Servers unknown tasks are isolated on
+        let (profiling_servers, dedicated) = self.profiling.servers(servers, &server_usage, weights);
+        let dedicated = dedicated && self.profiling.enabled;
+        let profiling_tasks: HashSet<TaskID> =
+            tasks.values().filter(|x| self.is_profiling(x)).map(|x| *x.id()).collect();
+
+        let mut server_nodes = HashMap::new();
     
         // 2. Add servers to flow graph
         for server in servers.values() {
             let node = graph.add_node(Node::Server(server.clone()));
 
             // 2.1. get profile based on benchmark
-            let cost = if let Some(profile) = server.profile().as_ref() {
+            let (cost, free) = if let Some(profile) = server.profile().as_ref() {
                  // 2.2 Get server usage, if server unused (not found) 0
                 let server_usage = server_usage.get(server.id()).map_or_else(Default::default, |x: &NormalizedResourceProfile| x.clone());
                 // 2.3 (MAX - profile + usage)
-                trace!("Server cost: {}: ({} - {:?} (cost {}) + {:?}(cost {})) ", server.hostname(), NormalizedResourceProfile::MAX.inner_product(), profile, profile.inner_product(), server_usage, server_usage.inner_product());
-                server_to_node.insert(server.id(), (profile.clone() - server_usage.clone(), node));
-                (NormalizedResourceProfile::MAX.inner_product() - profile.inner_product() + server_usage.inner_product()).scaled_i64()
+                trace!("Server cost: {}: ({} - {:?} (cost {}) + {:?}(cost {})) ", server.hostname(), max_cost, profile, profile.inner_product(weights), server_usage, server_usage.inner_product(weights));
+                let cost = (max_cost - profile.inner_product(weights) + server_usage.inner_product(weights)).scaled_i64();
+                (cost, profile.clone() - server_usage)
             } else {
-                i64::MAX
+                // Server without benchmark has no free resources for tasks with requests
+                (i64::MAX, NormalizedResourceProfile::default())
             };
             trace!("Cost result {}", cost);
-            graph.add_edge(
-                cluster,
-                node,
-                Capacity(task_count.try_into().unwrap()),
-                Cost(cost),
-            );
+            let allocatable = server
+                .allocatable()
+                .map(|x| x.saturating_sub(&requested.get(server.id()).copied().unwrap_or_default()));
+            server_nodes.insert(*server.id(), ServerNode { node, free, allocatable, cost });
+            // Dedicated profiling servers are reachable only by direct edges of profiled tasks,
+            // cordoned and draining servers don't accept new tasks
+            let dedicated_server = dedicated && profiling_servers.contains(server.id());
+            if !dedicated_server && states[server.id()] == ServerState::Active {
+                graph.add_edge(
+                    cluster,
+                    node,
+                    Capacity(task_count.try_into().unwrap()),
+                    Cost(cost),
+                );
+            }
             graph.add_edge(node, graph.sink, Capacity(task_count.try_into().unwrap()), Cost(0));
         }
+
+        let ctx = GraphContext {
+            cluster,
+            current,
+            states,
+            servers,
+            server_nodes,
+            colocated,
+            profiling_servers,
+            dedicated,
+            profiling_tasks,
+        };
     
         // 3. Add tasks to flow graph
         for task in tasks.values() {
@@ -229,53 +1259,236 @@ impl Scheduler {
             if !task.schedulable() {
                 continue;
             }
+            let weights = weight_config.for_class(task.class().as_ref());
             let cost = if let Some(server_id) = task.profiles().keys().next() {
                 // TODO: server can produce different profiles for the same task/job
                 // even though resource distribution is simillar this can result if having different
                 // cost when scheduling task
-                task.profile(server_id).map_or(0, |x| x.inner_product().to_i64().unwrap())
+                task.profile(server_id, &self.aggregation).map_or(0, |x| x.inner_product(weights).to_i64().unwrap())
             } else {
-                0
+                // Not profiled yet, use profile of similar tasks seen before
+                task.predicted().as_ref().map_or(0, |x| x.inner_product(weights).to_i64().unwrap())
             };
+            // Namespaces above their fair share are less likely to get resources
+            let cost = cost.saturating_add(fair_share_cost(task.namespace()));
+            // Users with larger dominant share yield to the least served ones
+            let cost = cost.saturating_add(drf_cost(task.user()));
 
             // 3.2 Create task and connect to source
             let task_node = graph.add_node(Node::Task(task.clone()));
             graph.add_edge(graph.source, task_node, Capacity(1), Cost(0));
     
             // 3.2 Connect task with servers
-            if let Some(request) = task.request() {
-                 // 3.2.1 Connect task with servers that meet requirements
-                 for server in servers.values() {
-                    let (free_resources, server_node) = &server_to_node[server.id()];
-                    let diff = free_resources.clone() - request.clone();
-                    if diff.has_negative_resource() {
-                        continue;
-                    }
-                    graph.add_edge(task_node, *server_node, Capacity(1), Cost(cost));
+            // Task is forced to move when constraints stopped being satisfied on its server
+            let current = current.get(task.id()).filter(|id| ctx.allows(task, &servers[*id]));
+            if let Some(id) = current {
+                // 3.2.1 Staying on the current server costs only the interference
+                let penalty = self.interference_penalty(&ctx, task, id);
+                graph.add_edge(task_node, ctx.server_nodes[id].node, Capacity(1), Cost(penalty));
+                let pinned = self
+                    .placed_for(task.id())
+                    .map_or(false, |x| x < self.migration_policy.min_residency());
+                if pinned {
+                    trace!("Task '{}' pinned to its server", task.name());
+                } else {
+                    // 3.2.2 Moving to other server is penalized by migration cost
+                    let cost = cost.saturating_add(self.migration_policy.cost());
+                    self.add_task_placement_edges(&mut graph, &ctx, task, task_node, cost);
                 }
             } else {
-                 // 3.2.2 Connect task with cluster node if no minimal requirements
-                 graph.add_edge(task_node, cluster, Capacity(1), Cost(cost.to_i64().unwrap()));
-                 if let Some(id) = self.schedule.get(task.id()) {
-                     graph.add_edge(task_node, server_to_node[id].1, Capacity(1), Cost(0));
-                 }
+                self.add_task_placement_edges(&mut graph, &ctx, task, task_node, cost);
             }
-            
-    
+
             // 3.3 Allow tasks to remain unscheduled
             let unscheduled =
                 graph.add_node(Node::VirtualResource(VirtualResource::new(format!("Unscheduled {}", task.name()))));
             graph.add_edge(task_node, unscheduled, Capacity(1), Cost(0));
+            // Waiting and nearing deadline makes leaving the task unscheduled costlier
+            let unscheduled_cost = max_cost * self.queue_priority(task, now);
             graph.add_edge(
                 unscheduled,
                 graph.sink,
                 Capacity(1),
-                Cost((NormalizedResourceProfile::MAX.inner_product()).scaled_i64()),
+                Cost(unscheduled_cost.scaled_i64()),
             );
         }
     
         graph
     }
+
+    /// Connects task with servers it can be moved to, the current server is skipped
+    fn add_task_placement_edges(
+        &self,
+        graph: &mut cost_flow::Graph<Node>,
+        ctx: &GraphContext,
+        task: &NormalizedTask,
+        task_node: cost_flow::NodeIndex,
+        cost: i64,
+    ) {
+        let penalties: HashMap<&ServerID, i64> = ctx
+            .servers
+            .keys()
+            .map(|x| (x, self.interference_penalty(ctx, task, x)))
+            .collect();
+        let unconstrained = task.request().is_none()
+            && task.requests().is_none()
+            && task.constraints().is_empty()
+            && !ctx.profiling_tasks.contains(task.id());
+        if unconstrained && penalties.values().all(|x| *x == 0) {
+            // Connect task with cluster node if no minimal requirements
+            graph.add_edge(task_node, ctx.cluster, Capacity(1), Cost(cost));
+            return;
+        }
+
+        // Connect task with servers that meet requirements and constraints
+        let current = ctx.current.get(task.id());
+        for server in ctx.servers.values() {
+            if Some(server.id()) == current {
+                continue;
+            }
+            let server_node = &ctx.server_nodes[server.id()];
+            if let Some(request) = task.request() {
+                let diff = server_node.free.clone() - request.clone();
+                if diff.has_negative_resource() {
+                    continue;
+                }
+            }
+            if let Some(requests) = task.requests() {
+                if !server_node.allocatable.map_or(false, |x| x.fits(requests)) {
+                    continue;
+                }
+            }
+            if !ctx.allows(task, server) {
+                continue;
+            }
+            // Tasks without request bypass cluster node so server load has to be accounted here
+            let cost =
+                if task.request().is_some() { cost } else { cost.saturating_add(server_node.cost) };
+            let cost = cost.saturating_add(penalties[server.id()]);
+            graph.add_edge(task_node, server_node.node, Capacity(1), Cost(cost));
+        }
+    }
+
+    /// Cost of expected slowdown between task and other tasks scheduled on the server
+    fn interference_penalty(
+        &self,
+        ctx: &GraphContext,
+        task: &NormalizedTask,
+        server: &ServerID,
+    ) -> i64 {
+        let signature = task.signature();
+        let colocated: Vec<String> = ctx
+            .colocated
+            .get(server)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .filter(|x| x.id() != task.id())
+            .map(|x| x.signature())
+            .collect();
+        self.interference.penalty(&signature, colocated.iter().map(|x| &x[..])).scaled_i64()
+    }
+
+    /// Removes newly placed tasks that violate constraints or exceed allocatable resources
+    /// because of other tasks placed on the same server in this round. Flow graph only checks
+    /// them against previous schedule.
+    fn enforce_constraints(
+        servers: &HashMap<ServerID, NormalizedServer>,
+        tasks: &HashMap<TaskID, NormalizedTask>,
+        old: &HashMap<TaskID, ServerID>,
+        schedule: &mut HashMap<TaskID, ServerID>,
+    ) {
+        let mut colocated: HashMap<ServerID, Vec<&NormalizedTask>> = HashMap::new();
+        let mut requested: HashMap<ServerID, Resources> = HashMap::new();
+        let mut placed = vec![];
+        for (task_id, server_id) in schedule.iter() {
+            if old.get(task_id) == Some(server_id) {
+                colocated.entry(*server_id).or_default().push(&tasks[task_id]);
+                *requested.entry(*server_id).or_default() +=
+                    tasks[task_id].requests().unwrap_or_default();
+            } else {
+                placed.push(*task_id);
+            }
+        }
+        placed.sort();
+
+        for task_id in placed {
+            let server_id = schedule[&task_id];
+            let task = &tasks[&task_id];
+            let any_matching = |selector: &placement::Labels| {
+                colocated.values().flatten().any(|x| placement::matches(selector, x.labels()))
+            };
+            let server = &servers[&server_id];
+            let allowed = task.constraints().allows(
+                task,
+                server,
+                colocated.get(&server_id).map_or(&[][..], Vec::as_slice),
+                any_matching,
+            );
+            let fits = task.requests().map_or(true, |requests| {
+                let used = requested.get(&server_id).copied().unwrap_or_default();
+                server.allocatable().map_or(false, |x| x.saturating_sub(&used).fits(requests))
+            });
+            if allowed && fits {
+                colocated.entry(server_id).or_default().push(task);
+                *requested.entry(server_id).or_default() += task.requests().unwrap_or_default();
+            } else {
+                debug!("Task '{}' violates constraints of newly placed tasks", task.name());
+                schedule.remove(&task_id);
+            }
+        }
+    }
+}
+
+struct ServerNode {
+    node: cost_flow::NodeIndex,
+    /// Benchmark profile without resources used by scheduled tasks
+    free: NormalizedResourceProfile,
+    /// Allocatable resources without requests of scheduled tasks
+    allocatable: Option<Resources>,
+    /// Cost of the cluster to server edge
+    cost: i64,
+}
+
+/// Shared state used when connecting tasks to servers
+struct GraphContext<'a> {
+    cluster: cost_flow::NodeIndex,
+    /// Schedule the graph is built for
+    current: &'a HashMap<TaskID, ServerID>,
+    /// Availability of servers
+    states: &'a HashMap<ServerID, ServerState>,
+    servers: &'a HashMap<ServerID, NormalizedServer>,
+    server_nodes: HashMap<ServerID, ServerNode>,
+    /// Tasks currently scheduled on server
+    colocated: HashMap<ServerID, Vec<&'a NormalizedTask>>,
+    /// Servers tasks with unknown profile are placed on
+    profiling_servers: HashSet<ServerID>,
+    /// Profiling servers don't run other tasks
+    dedicated: bool,
+    /// Tasks being profiled
+    profiling_tasks: HashSet<TaskID>,
+}
+
+impl GraphContext<'_> {
+    fn allows(&self, task: &NormalizedTask, server: &NormalizedServer) -> bool {
+        match self.states[server.id()] {
+            ServerState::Active => {}
+            // Running tasks stay on cordoned server
+            ServerState::Cordoned if self.current.get(task.id()) == Some(server.id()) => {}
+            ServerState::Cordoned | ServerState::Draining => return false,
+        }
+        let profiling_server = self.profiling_servers.contains(server.id());
+        if self.profiling_tasks.contains(task.id()) {
+            if !profiling_server {
+                return false;
+            }
+        } else if self.dedicated && profiling_server {
+            return false;
+        }
+        let colocated = self.colocated.get(server.id()).map_or(&[][..], Vec::as_slice);
+        task.constraints().allows(task, server, colocated, |selector| {
+            self.colocated.values().flatten().any(|x| placement::matches(selector, x.labels()))
+        })
+    }
 }
 
 trait DecimalConvert {
@@ -286,4 +1499,254 @@ impl DecimalConvert for Decimal {
     fn scaled_i64(&self) -> i64 {
         (self * Decimal::new(100, 0)).to_i64().unwrap()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::State;
+
+    /// Data directory of a test, removed when the test ends also if an assert fails
+    struct TempDir(std::path::PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Channel the agent of a server receives commands from
+    type Agent = mpsc::Receiver<TaskCommand>;
+
+    /// Scheduler with `n` subscribed servers, returned with their agents in order. Agents have
+    /// to be kept alive, a dropped agent counts as disconnected.
+    fn scheduler_with_servers(n: usize) -> (Scheduler, Vec<(ServerID, Agent)>, TempDir) {
+        let dir = TempDir(std::env::temp_dir().join(format!("scheduler-test-{}", Uuid::new_v4())));
+        let mut scheduler = Scheduler::new(&dir.0);
+        let servers = (0..n)
+            .map(|i| {
+                let id = Uuid::new_v4();
+                let (tx, rx) = mpsc::channel(10);
+                scheduler.subscribe_server(id, tx);
+                scheduler.servers.insert(id, Server::new(id, format!("server-{}", i), None));
+                (id, rx)
+            })
+            .collect();
+        (scheduler, servers, dir)
+    }
+
+    /// Adds task `name` without admission and returns its id
+    fn add_task(scheduler: &mut Scheduler, name: &str) -> TaskID {
+        let task = Task::new(name.to_string(), None, "image".to_string(), false, None);
+        let id = *task.id();
+        scheduler.tasks.insert(id, task);
+        id
+    }
+
+    /// Applies plan changing placement of `task` to `server`, descheduling it if none
+    async fn place(scheduler: &mut Scheduler, task: TaskID, server: Option<ServerID>) {
+        let schedule = server.map(|x| (task, x)).into_iter().collect();
+        let plan = Plan::new(&scheduler.schedule, schedule, 0);
+        scheduler.apply_plan(plan, &Carrier::new()).await;
+    }
+
+    #[tokio::test]
+    async fn moved_task_is_removed_from_old_server() {
+        let (mut scheduler, mut agents, _dir) = scheduler_with_servers(2);
+        let (a, b) = (agents[0].0, agents[1].0);
+        let id = add_task(&mut scheduler, "t");
+
+        // Task moves to b and back to a
+        for server in &[a, b, a] {
+            place(&mut scheduler, id, Some(*server)).await;
+        }
+
+        let mut commands = |i: usize| {
+            let mut states = vec![];
+            while let Ok(Some(cmd)) = agents[i].1.try_next() {
+                assert_eq!(*cmd.task.id(), id);
+                states.push(cmd.state);
+            }
+            states
+        };
+        assert_eq!(commands(0), vec![State::Run, State::Remove, State::Run]);
+        assert_eq!(commands(1), vec![State::Run, State::Remove]);
+    }
+
+    #[tokio::test]
+    async fn residency_measured_by_clock() {
+        let (mut scheduler, agents, _dir) = scheduler_with_servers(1);
+        let start = chrono::Utc::now();
+        let clock = Arc::new(super::super::ManualClock::new(start));
+        scheduler.set_clock(clock.clone());
+        let id = add_task(&mut scheduler, "t");
+
+        place(&mut scheduler, id, Some(agents[0].0)).await;
+        clock.set(start + chrono::Duration::minutes(10));
+        assert_eq!(scheduler.placed_for(&id), Some(Duration::from_secs(600)));
+    }
+
+    #[tokio::test]
+    async fn runtime_includes_time_before_move() {
+        let (mut scheduler, agents, _dir) = scheduler_with_servers(2);
+        let start = chrono::Utc::now();
+        let clock = Arc::new(super::super::ManualClock::new(start));
+        scheduler.set_clock(clock.clone());
+        let id = add_task(&mut scheduler, "t");
+
+        place(&mut scheduler, id, Some(agents[0].0)).await;
+        clock.set(start + chrono::Duration::minutes(10));
+        place(&mut scheduler, id, Some(agents[1].0)).await;
+        clock.set(start + chrono::Duration::minutes(15));
+        assert_eq!(scheduler.placed_for(&id), Some(Duration::from_secs(300)));
+        assert_eq!(scheduler.running_for(&id), Some(Duration::from_secs(900)));
+    }
+
+    #[tokio::test]
+    async fn drained_after_removal_is_confirmed() {
+        let (mut scheduler, agents, _dir) = scheduler_with_servers(2);
+        let (a, b) = (agents[0].0, agents[1].0);
+        scheduler.servers.get_mut(&a).unwrap().set_state(ServerState::Draining);
+        let id = add_task(&mut scheduler, "t");
+        let now = chrono::Utc::now();
+
+        place(&mut scheduler, id, Some(a)).await;
+        place(&mut scheduler, id, Some(b)).await;
+        assert!(!scheduler.is_drained(&scheduler.servers[&a], now));
+
+        scheduler.confirm_removal(&a, &id);
+        assert!(scheduler.is_drained(&scheduler.servers[&a], now));
+        assert!(!scheduler.is_drained(&scheduler.servers[&b], now));
+    }
+
+    #[tokio::test]
+    async fn quota_admits_resubmitted_task() {
+        let (mut scheduler, _, _dir) = scheduler_with_servers(0);
+        let mut namespace = Namespace::new(super::super::namespace::DEFAULT.to_string());
+        namespace.quota.tasks = Some(1);
+        scheduler.namespaces.insert(namespace.name.clone(), namespace);
+        add_task(&mut scheduler, "job");
+
+        let resubmitted = Task::new("job".to_string(), None, "image".to_string(), false, None);
+        let other = Task::new("other".to_string(), None, "image".to_string(), false, None);
+        assert!(scheduler.insert_task(resubmitted).await.is_ok());
+        assert_eq!(scheduler.tasks.len(), 1);
+        assert!(scheduler.admit(&other, &[]).is_err());
+    }
+
+    #[test]
+    fn workload_replicas_respect_namespace_quota() {
+        let (mut scheduler, _, _dir) = scheduler_with_servers(0);
+        let mut namespace = Namespace::new("team".to_string());
+        namespace.quota.tasks = Some(1);
+        scheduler.namespaces.insert(namespace.name.clone(), namespace);
+        let kind = super::super::WorkloadKind::Service;
+        let mut workload =
+            Workload::new("web".to_string(), kind, "image".to_string(), None, false, None, 2);
+        workload.set_namespace("team".to_string());
+        scheduler.workloads.insert(*workload.id(), workload);
+
+        scheduler.reconcile_workloads();
+        assert_eq!(scheduler.tasks.len(), 1);
+        assert!(scheduler.tasks.values().all(|x| x.namespace() == "team"));
+    }
+
+    #[tokio::test]
+    async fn new_leader_restores_placed_tasks() {
+        let (mut leader, agents, dir) = scheduler_with_servers(1);
+        let server = agents[0].0;
+        let id = add_task(&mut leader, "t");
+        place(&mut leader, id, Some(server)).await;
+        for snapshot in leader.snapshots() {
+            snapshot.write().await;
+        }
+
+        let mut replica = Scheduler::new(&dir.0);
+        replica.restore(&dir.0);
+        assert!(replica.servers.contains_key(&server));
+        assert!(replica.tasks.contains_key(&id));
+        assert_eq!(replica.schedule.get(&id), Some(&server));
+    }
+
+    #[tokio::test]
+    async fn restored_leader_schedules_before_agents_subscribe() {
+        let (mut leader, agents, dir) = scheduler_with_servers(1);
+        let server = agents[0].0;
+        let id = add_task(&mut leader, "t");
+        place(&mut leader, id, Some(server)).await;
+        leader.profiled.insert(id);
+        leader.removing.entry(server).or_default().insert(Uuid::new_v4());
+        for snapshot in leader.snapshots() {
+            snapshot.write().await;
+        }
+
+        let mut replica = Scheduler::new(&dir.0);
+        replica.restore(&dir.0);
+        assert!(replica.profiled.contains(&id));
+        assert_eq!(replica.removing, leader.removing);
+        replica.schedule().await;
+        place(&mut replica, id, None).await;
+        assert!(replica.undelivered.contains_key(&server));
+
+        let (tx, mut rx) = mpsc::channel(10);
+        replica.subscribe_server(server, tx);
+        let cmd = rx.try_next().unwrap().unwrap();
+        assert_eq!(cmd.task.id(), &id);
+        assert_eq!(cmd.state, State::Remove);
+        assert!(!replica.undelivered.contains_key(&server));
+    }
+
+    #[tokio::test]
+    async fn placement_trace_is_accepted_once() {
+        let (mut scheduler, agents, _dir) = scheduler_with_servers(1);
+        let id = add_task(&mut scheduler, "t");
+        place(&mut scheduler, id, Some(agents[0].0)).await;
+        assert!(scheduler.traces.contains_key(&id));
+
+        scheduler.accept_task(&id);
+        assert!(!scheduler.traces.contains_key(&id));
+    }
+
+    #[test]
+    fn solvers_agree_on_scheduling_graph() {
+        let (mut scheduler, _, _dir) = scheduler_with_servers(0);
+        let profile = |ipc: i64, memory: i64| -> ResourceProfile {
+            vec![(dimension::IPC, Decimal::from(ipc)), (dimension::MEMORY, Decimal::from(memory))]
+                .into_iter()
+                .collect()
+        };
+        // Server without benchmark is reached over edge of infinite cost
+        let benchmarks =
+            vec![("a", Some(profile(10, 100))), ("b", Some(profile(5, 50))), ("c", None)];
+        for (hostname, benchmark) in benchmarks {
+            let id = Uuid::new_v4();
+            scheduler.servers.insert(id, Server::new(id, hostname.to_string(), benchmark));
+        }
+        let requests =
+            vec![("small", Some(profile(2, 10))), ("large", Some(profile(6, 60))), ("any", None)];
+        for (name, request) in requests {
+            let task = Task::new(name.to_string(), request, "image".to_string(), false, None);
+            scheduler.tasks.insert(*task.id(), task);
+        }
+        let (servers, tasks) = scheduler.normalize();
+        let states = servers.keys().map(|x| (*x, ServerState::Active)).collect();
+
+        let solve = |solver: Solver| {
+            let current = HashMap::new();
+            let mut graph =
+                scheduler.build_flow_graph(&servers, &tasks, &current, &states, &scheduler.weights);
+            solver.solve(&mut graph);
+            let edges = graph.all_edges();
+            let cost =
+                edges.iter().map(|x| x.flow.saturating_mul(x.cost)).fold(0, u64::saturating_add);
+            let flow: u64 = edges
+                .iter()
+                .filter(|x| matches!(x.target, cost_flow::Node::Sink))
+                .map(|x| x.flow)
+                .sum();
+            (cost, flow)
+        };
+        let expected = solve(Solver::CycleCancelling);
+        assert_eq!(expected.1, 3);
+        assert_eq!(solve(Solver::SuccessiveShortestPath), expected);
+    }
+}
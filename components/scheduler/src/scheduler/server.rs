@@ -1,8 +1,13 @@
+use super::maintenance::{self, MaintenanceWindow, ServerState};
+use super::placement::Labels;
+use super::Resources;
 use crate::prelude::*;
 use cost_flow::Graphable;
 use getset::{Getters, Setters};
 
-#[derive(PartialOrd, PartialEq, Clone, Debug, Serialize, Eq, Ord, Hash, Getters, Setters)]
+#[derive(
+    PartialOrd, PartialEq, Clone, Debug, Serialize, Deserialize, Eq, Ord, Hash, Getters, Setters,
+)]
 pub struct Server<T> {
     #[get = "pub"]
     id: Uuid,
@@ -10,20 +15,71 @@ pub struct Server<T> {
     hostname: String,
     #[getset(get = "pub", set = "pub")]
     profile: Option<T>,
+    /// Reported by agent during registration or set through api
+    #[getset(get = "pub", set = "pub")]
+    labels: Labels,
+    /// Total resources of the machine reported by agent
+    #[getset(get = "pub", set = "pub")]
+    capacity: Option<Resources>,
+    /// Resources kept for the system and agent itself
+    #[getset(get = "pub", set = "pub")]
+    reserved: Resources,
+    /// Highest task profile observed on this server
+    #[getset(get = "pub", set = "pub")]
+    peak: Option<T>,
+    #[getset(get = "pub", set = "pub")]
+    state: ServerState,
+    /// Planned maintenance windows, past ones are kept for reference
+    #[getset(get = "pub", set = "pub")]
+    maintenance: Vec<MaintenanceWindow>,
 }
 
 impl<T> Server<T> {
     pub fn new(id: Uuid, hostname: String, profile: Option<T>) -> Self {
-        Self { hostname, profile, id }
+        Self {
+            hostname,
+            profile,
+            id,
+            labels: Default::default(),
+            capacity: None,
+            reserved: Default::default(),
+            peak: None,
+            state: ServerState::Active,
+            maintenance: vec![],
+        }
+    }
+
+    /// State at `now` taking maintenance windows into account
+    pub fn effective_state(&self, now: chrono::DateTime<chrono::Utc>) -> ServerState {
+        maintenance::effective_state(self.state, &self.maintenance, now)
+    }
+
+    /// Resources that can be requested by tasks, `None` if capacity is unknown
+    pub fn allocatable(&self) -> Option<Resources> {
+        self.capacity.map(|x| x.saturating_sub(&self.reserved))
     }
 }
 
 impl Server<super::ResourceProfile> {
+    /// Benchmark profile raised by task peaks exceeding it
+    pub fn effective_profile(&self) -> Option<super::ResourceProfile> {
+        match (&self.profile, &self.peak) {
+            (Some(profile), Some(peak)) => Some(profile.max_by_resource(peak)),
+            (profile, _) => profile.clone(),
+        }
+    }
+
     pub fn normalize(&self, max_profile: &super::ResourceProfile) -> super::NormalizedServer {
         super::NormalizedServer {
-            profile: self.profile.map(|x| x.normalize(max_profile)),
+            profile: self.effective_profile().map(|x| x.normalize(max_profile)),
             hostname: self.hostname.clone(),
             id: self.id,
+            labels: self.labels.clone(),
+            capacity: self.capacity,
+            reserved: self.reserved,
+            peak: self.peak.as_ref().map(|x| x.normalize(max_profile)),
+            state: self.state,
+            maintenance: self.maintenance.clone(),
         }
     }
 }
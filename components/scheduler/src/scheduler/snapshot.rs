@@ -0,0 +1,37 @@
+use crate::prelude::*;
+use std::path::PathBuf;
+
+/// Serialized learned state, written to disk outside of the scheduler lock
+pub struct Snapshot {
+    path: PathBuf,
+    content: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Snapshot of `state` to be written to `path`, none if it can't be serialized
+    pub fn new(path: &Path, state: &impl Serialize) -> Option<Self> {
+        match serde_json::to_vec(state) {
+            Ok(content) => Some(Self { path: path.to_path_buf(), content }),
+            Err(e) => {
+                error!("Can't serialize '{}': {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Replaces the file, errors are logged as the state stays in memory and is written again
+    /// with its next change. Renamed file is never seen half written.
+    pub async fn write(self) {
+        let result = async {
+            if let Some(dir) = self.path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            let tmp = self.path.with_extension("tmp");
+            tokio::fs::write(&tmp, &self.content).await?;
+            tokio::fs::rename(&tmp, &self.path).await
+        };
+        if let Err(e) = result.await {
+            error!("Can't write '{}': {}", self.path.display(), e);
+        }
+    }
+}
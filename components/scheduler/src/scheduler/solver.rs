@@ -0,0 +1,41 @@
+use crate::prelude::*;
+use cost_flow::{Graph, Graphable, MinimumCostFlow, SuccessiveShortestPath};
+use std::fmt::Debug;
+
+/// Algorithm solving the flow graph of a scheduling round
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Solver {
+    /// Maximum flow followed by cancelling negative cost cycles
+    CycleCancelling,
+    /// Augments along cheapest paths
+    SuccessiveShortestPath,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver::CycleCancelling
+    }
+}
+
+impl FromStr for Solver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cycle_cancelling" => Ok(Solver::CycleCancelling),
+            "successive_shortest_path" => Ok(Solver::SuccessiveShortestPath),
+            _ => Err(format!("unknown solver '{}'", s)),
+        }
+    }
+}
+
+impl Solver {
+    /// Sets minimum cost maximum flow on `graph`
+    pub fn solve<T: Clone + Debug + Graphable>(self, graph: &mut Graph<T>) {
+        match self {
+            Solver::CycleCancelling => graph.minimum_cost_flow(),
+            Solver::SuccessiveShortestPath => graph.successive_shortest_path(),
+        }
+    }
+}
@@ -1,10 +1,13 @@
+use super::placement::{Constraints, Labels};
+use super::Resources;
+use super::WorkloadClass;
 use crate::prelude::*;
 use cost_flow::Graphable;
 use getset::{Getters, Setters};
 use std::hash::Hash;
 use std::hash::Hasher;
 
-#[derive(Clone, Debug, Serialize, Eq, Getters, Setters)]
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, Getters, Setters)]
 pub struct Task<T> {
     #[getset(get = "pub")]
     id: Uuid,
@@ -23,6 +26,54 @@ pub struct Task<T> {
     /// Signals finished task
     #[getset(get = "pub", set = "pub")]
     schedulable: bool,
+    /// Job or service this task is replica of
+    #[getset(get = "pub", set = "pub")]
+    owner: Option<Uuid>,
+    /// Revision of the owner the task was created from
+    #[getset(get = "pub", set = "pub")]
+    revision: u64,
+    /// Sequence number of the instance among instances of the owner
+    #[getset(get = "pub", set = "pub")]
+    instance: u64,
+    /// Tasks of the same gang are started only if all of them can be placed
+    #[getset(get = "pub", set = "pub")]
+    gang: Option<String>,
+    /// Used by affinity rules of other tasks
+    #[getset(get = "pub", set = "pub")]
+    labels: Labels,
+    #[getset(get = "pub", set = "pub")]
+    constraints: Constraints,
+    /// Class selecting resource weights used for the task cost
+    #[getset(get = "pub", set = "pub")]
+    class: Option<String>,
+    /// Resource the task is bound by, declared on submission or classified from past profiles
+    /// of its image
+    #[getset(get = "pub", set = "pub")]
+    workload_class: Option<WorkloadClass>,
+    /// Absolute resources reserved for the task on its server
+    #[getset(get = "pub", set = "pub")]
+    requests: Option<Resources>,
+    /// Absolute resources the task container is limited to
+    #[getset(get = "pub", set = "pub")]
+    limits: Option<Resources>,
+    /// Profile expected from history of similar tasks, used until the task is profiled
+    #[getset(get = "pub", set = "pub")]
+    predicted: Option<T>,
+    /// Project owning the task, task names are unique within namespace
+    #[getset(get = "pub", set = "pub")]
+    namespace: String,
+    /// User who submitted the task, tasks of users share the cluster by dominant resource fairness
+    #[getset(get = "pub", set = "pub")]
+    user: Option<String>,
+    /// Exit code of the finished container
+    #[getset(get = "pub", set = "pub")]
+    exit_code: Option<i64>,
+    /// Docker volume for artifacts shared with other tasks of the workflow
+    #[getset(get = "pub", set = "pub")]
+    volume: Option<String>,
+    /// Time by which the task should start, leaving it unscheduled gets costlier as it nears
+    #[getset(get = "pub", set = "pub")]
+    deadline: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl<T> Task<T> {
@@ -42,13 +93,40 @@ impl<T> Task<T> {
             id: Uuid::new_v4(),
             profiles: Default::default(),
             schedulable: true,
+            owner: None,
+            revision: 0,
+            instance: 0,
+            gang: None,
+            labels: Default::default(),
+            constraints: Default::default(),
+            class: None,
+            workload_class: None,
+            requests: None,
+            limits: None,
+            predicted: None,
+            namespace: super::namespace::DEFAULT.to_string(),
+            user: None,
+            exit_code: None,
+            volume: None,
+            deadline: None,
+        }
+    }
+}
+
+impl<T> Task<T> {
+    /// Identifies tasks running the same workload, shared by all instances of an image and
+    /// command
+    pub fn signature(&self) -> String {
+        match &self.cmd {
+            Some(cmd) => format!("{} {}", self.image, cmd),
+            None => self.image.clone(),
         }
     }
 }
 
 impl<T> PartialEq for Task<T> {
     fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
+        self.namespace == other.namespace && self.name == other.name
     }
 }
 
@@ -65,7 +143,7 @@ impl Task<super::ResourceProfile> {
             image: self.image.clone(),
             realtime: self.realtime,
             name: self.name.clone(),
-            request: self.request.map(|x| x.normalize(&max_profile)),
+            request: self.request.as_ref().map(|x| x.normalize(&max_profile)),
             cmd: self.cmd.clone(),
             profiles: self
                 .profiles
@@ -73,44 +151,54 @@ impl Task<super::ResourceProfile> {
                 .map(|x| (*x.0, x.1.iter().map(|x| x.normalize(max_profile)).collect()))
                 .collect(),
             schedulable: self.schedulable,
+            owner: self.owner,
+            revision: self.revision,
+            instance: self.instance,
+            gang: self.gang.clone(),
+            labels: self.labels.clone(),
+            constraints: self.constraints.clone(),
+            class: self.class.clone(),
+            workload_class: self.workload_class,
+            requests: self.requests,
+            limits: self.limits,
+            predicted: self.predicted.as_ref().map(|x| x.normalize(max_profile)),
+            namespace: self.namespace.clone(),
+            user: self.user.clone(),
+            exit_code: self.exit_code,
+            volume: self.volume.clone(),
+            deadline: self.deadline,
         }
     }
 
-    pub fn insert_profile(&mut self, server_id: Uuid, profile: super::ResourceProfile) {
+    /// Records profile sample, only `retention` newest samples per server are kept
+    pub fn insert_profile(&mut self, server_id: Uuid, profile: super::ResourceProfile, retention: usize) {
         let entry = self.profiles.entry(server_id).or_insert_with(|| vec![]);
         (*entry).push(profile);
+        let excess = entry.len().saturating_sub(retention.max(1));
+        entry.drain(..excess);
     }
 }
 
 impl Task<super::ResourceProfile> {
+    /// Mean of all samples across servers
     pub fn debug_profile(&self) -> super::ResourceProfile {
-        self.profiles
-            .values()
-            .flatten()
-            .fold(Default::default(), |acc, x| (acc + *x) / super::ResourceProfile::two())
+        let samples: Vec<_> = self.profiles.values().flatten().collect();
+        if samples.is_empty() {
+            return Default::default();
+        }
+        let sum = samples.iter().fold(super::ResourceProfile::default(), |acc, x| acc + (*x).clone());
+        sum / Decimal::from(samples.len() as u64)
     }
 }
 
 impl Task<super::NormalizedResourceProfile> {
-    pub fn profile(&self, server_id: &Uuid) -> Option<super::NormalizedResourceProfile> {
-        use num_traits::cast::FromPrimitive;
-
-        let length = self.profiles.get(server_id).map_or(0, Vec::len);
-        if let Some(profile) = self.profiles.get(server_id) {
-            let sum = profile.iter().enumerate().fold(
-                super::NormalizedResourceProfile::default(),
-                |acc, (i, x)| {
-                    #[allow(clippy::cast_precision_loss)]
-                    let i = i as f64;
-                    #[allow(clippy::cast_precision_loss)]
-                    let length = length as f64;
-                    acc + (x.clone() * Decimal::from_f64(1_f64 + i / length).unwrap())
-                },
-            );
-            Some(sum / Decimal::new(length.try_into().unwrap(), 0))
-        } else {
-            None
-        }
+    /// Profile observed on `server_id` aggregated according to `policy`
+    pub fn profile(
+        &self,
+        server_id: &Uuid,
+        policy: &super::AggregationPolicy,
+    ) -> Option<super::NormalizedResourceProfile> {
+        self.profiles.get(server_id).and_then(|x| policy.aggregate(x))
     }
 }
 
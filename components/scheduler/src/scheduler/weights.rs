@@ -0,0 +1,64 @@
+use crate::prelude::*;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Importance of each resource dimension when converting profile to a cost.
+///
+/// Dimensions without weight are ignored
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Weights(BTreeMap<String, Decimal>);
+
+impl Weights {
+    pub fn new(weights: BTreeMap<String, Decimal>) -> Self {
+        Self(weights)
+    }
+
+    pub fn get(&self, dimension: &str) -> Decimal {
+        self.0.get(dimension).copied().unwrap_or_default()
+    }
+
+    /// Names of weighted dimensions
+    pub fn dimensions(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(|x| &x[..])
+    }
+}
+
+impl Default for Weights {
+    /// Cpu and memory are scarcer than disk and network on a typical server
+    fn default() -> Self {
+        Self(
+            vec![
+                ("ipc".to_string(), Decimal::new(2, 0)),
+                ("memory".to_string(), Decimal::new(2, 0)),
+                ("network".to_string(), Decimal::new(1, 0)),
+                ("disk".to_string(), Decimal::new(1, 0)),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+}
+
+/// Cluster wide weights with overrides for task classes
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WeightConfig {
+    #[serde(default)]
+    pub cluster: Weights,
+    #[serde(default)]
+    pub classes: HashMap<String, Weights>,
+}
+
+impl WeightConfig {
+    /// Loads weights from json file at `path`, defaults are used if file doesn't exist
+    pub fn load(path: &PathBuf) -> BoxResult<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+
+    /// Weights of task class, cluster weights if class has no override
+    pub fn for_class(&self, class: Option<&String>) -> &Weights {
+        class.and_then(|x| self.classes.get(x)).unwrap_or(&self.cluster)
+    }
+}
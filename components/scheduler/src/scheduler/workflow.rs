@@ -0,0 +1,319 @@
+use super::{AdmissionError, ResourceProfile, Task};
+use crate::prelude::*;
+use std::collections::HashSet;
+
+/// What happens to the rest of a workflow when one of its tasks fails
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Cancels all tasks that haven't finished yet
+    FailFast,
+    /// Cancels only tasks depending on the failed one, other branches keep running
+    Continue,
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        FailurePolicy::FailFast
+    }
+}
+
+impl FromStr for FailurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail_fast" => Ok(FailurePolicy::FailFast),
+            "continue" => Ok(FailurePolicy::Continue),
+            _ => Err(format!("unknown failure policy '{}'", s)),
+        }
+    }
+}
+
+/// Progress of a task run by a workflow or scheduled task
+#[derive(Clone, Copy, Debug, Serialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    /// Some dependencies haven't succeeded yet
+    Waiting,
+    /// Waits for a server
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    /// Won't run because of a failure or was removed
+    Cancelled,
+}
+
+impl TaskStatus {
+    /// Status of `task` not waiting for anything, `running` if it's placed on a server
+    pub fn of(task: &Task<ResourceProfile>, running: bool) -> Self {
+        match task.exit_code() {
+            Some(0) => TaskStatus::Succeeded,
+            Some(_) => TaskStatus::Failed,
+            None if !task.schedulable() => TaskStatus::Cancelled,
+            None if running => TaskStatus::Running,
+            None => TaskStatus::Pending,
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            TaskStatus::Waiting => "gray",
+            TaskStatus::Pending => "orange",
+            TaskStatus::Running => "blue",
+            TaskStatus::Succeeded => "green",
+            TaskStatus::Failed => "red",
+            TaskStatus::Cancelled => "black",
+        }
+    }
+}
+
+/// Tasks submitted together with dependencies between them, a single task is a DAG
+/// without edges
+#[derive(Clone, Debug)]
+pub struct Dag {
+    pub name: String,
+    pub tasks: Vec<Task<ResourceProfile>>,
+    /// Task name -> names of tasks that have to succeed before it starts
+    pub dependencies: HashMap<String, Vec<String>>,
+    pub failure_policy: FailurePolicy,
+    /// Docker volume mounted to all tasks of the workflow to pass artifacts between them,
+    /// tasks on different servers need a volume driver backed by shared storage
+    pub volume: Option<String>,
+}
+
+impl From<Task<ResourceProfile>> for Dag {
+    fn from(task: Task<ResourceProfile>) -> Self {
+        Self {
+            name: task.name().clone(),
+            tasks: vec![task],
+            dependencies: Default::default(),
+            failure_policy: Default::default(),
+            volume: None,
+        }
+    }
+}
+
+impl Dag {
+    /// Single task without volume isn't tracked as a workflow
+    pub fn is_workflow(&self) -> bool {
+        self.tasks.len() > 1 || !self.dependencies.is_empty() || self.volume.is_some()
+    }
+
+    /// Checks that dependencies refer to tasks of the DAG and contain no cycle
+    pub fn validate(&self) -> Result<(), AdmissionError> {
+        let names: HashSet<&String> = self.tasks.iter().map(|x| x.name()).collect();
+        if names.len() != self.tasks.len() {
+            return Err(AdmissionError(format!("Workflow '{}' has duplicate task names", self.name)));
+        }
+        for (task, dependencies) in &self.dependencies {
+            if let Some(name) = std::iter::once(task).chain(dependencies).find(|x| !names.contains(x)) {
+                return Err(AdmissionError(format!("Workflow '{}' has no task '{}'", self.name, name)));
+            }
+        }
+
+        // Removes tasks without unresolved dependencies until none is left, the rest forms a cycle
+        let mut remaining: HashMap<&String, HashSet<&String>> = names
+            .iter()
+            .map(|x| (*x, self.dependencies.get(*x).map(|x| x.iter().collect()).unwrap_or_default()))
+            .collect();
+        loop {
+            let ready: Vec<&String> =
+                remaining.iter().filter(|(_, x)| x.is_empty()).map(|(k, _)| *k).collect();
+            if ready.is_empty() {
+                break;
+            }
+            for name in ready {
+                remaining.remove(name);
+                remaining.values_mut().for_each(|x| {
+                    x.remove(name);
+                });
+            }
+        }
+        if !remaining.is_empty() {
+            let mut cycle: Vec<&str> = remaining.keys().map(|x| &x[..]).collect();
+            cycle.sort();
+            return Err(AdmissionError(format!(
+                "Workflow '{}' has a dependency cycle between {}",
+                self.name,
+                cycle.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// DAG of tasks inserted into the scheduler
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Workflow {
+    pub id: Uuid,
+    pub name: String,
+    pub failure_policy: FailurePolicy,
+    pub volume: Option<String>,
+    /// Task -> tasks it depends on, contains all tasks of the workflow
+    pub dependencies: HashMap<Uuid, Vec<Uuid>>,
+    /// Tasks that won't run because of a failure
+    pub cancelled: HashSet<Uuid>,
+}
+
+impl Workflow {
+    /// Workflow of `dag` whose tasks got `ids` by name
+    pub fn new(dag: &Dag, ids: &HashMap<String, Uuid>) -> Self {
+        let dependencies = dag
+            .tasks
+            .iter()
+            .map(|task| {
+                let dependencies = dag.dependencies.get(task.name()).map_or(&[][..], Vec::as_slice);
+                (ids[task.name()], dependencies.iter().map(|x| ids[x]).collect())
+            })
+            .collect();
+        Self {
+            id: Uuid::new_v4(),
+            name: dag.name.clone(),
+            failure_policy: dag.failure_policy,
+            volume: dag.volume.clone(),
+            dependencies,
+            cancelled: Default::default(),
+        }
+    }
+
+    /// Task waits until all its dependencies succeed
+    pub fn is_blocked(&self, id: &Uuid, tasks: &HashMap<Uuid, Task<ResourceProfile>>) -> bool {
+        self.cancelled.contains(id)
+            || self.dependencies.get(id).map_or(false, |x| {
+                x.iter().any(|x| tasks.get(x).map_or(true, |x| *x.exit_code() != Some(0)))
+            })
+    }
+
+    /// Unfinished tasks that won't run after failures of the workflow according to its policy
+    pub fn to_cancel(&self, tasks: &HashMap<Uuid, Task<ResourceProfile>>) -> Vec<Uuid> {
+        let mut failed: Vec<Uuid> = self
+            .dependencies
+            .keys()
+            .filter(|x| tasks.get(x).map_or(false, |x| x.exit_code().map_or(false, |x| x != 0)))
+            .copied()
+            .collect();
+        if failed.is_empty() {
+            return vec![];
+        }
+        let affected: HashSet<Uuid> = match self.failure_policy {
+            FailurePolicy::FailFast => self.dependencies.keys().copied().collect(),
+            FailurePolicy::Continue => {
+                // Tasks depending on failed ones, directly or transitively
+                let mut dependents = HashSet::new();
+                while let Some(id) = failed.pop() {
+                    for (task, dependencies) in &self.dependencies {
+                        if dependencies.contains(&id) && dependents.insert(*task) {
+                            failed.push(*task);
+                        }
+                    }
+                }
+                dependents
+            }
+        };
+        let mut cancel: Vec<Uuid> = affected
+            .into_iter()
+            .filter(|x| !self.cancelled.contains(x))
+            .filter(|x| tasks.get(x).map_or(false, |x| *x.schedulable()))
+            .collect();
+        cancel.sort();
+        cancel
+    }
+
+    pub fn status(
+        &self,
+        id: &Uuid,
+        tasks: &HashMap<Uuid, Task<ResourceProfile>>,
+        schedule: &HashMap<Uuid, Uuid>,
+    ) -> TaskStatus {
+        let task = match tasks.get(id) {
+            Some(task) => task,
+            None => return TaskStatus::Cancelled,
+        };
+        match TaskStatus::of(task, schedule.contains_key(id)) {
+            _ if self.cancelled.contains(id) => TaskStatus::Cancelled,
+            TaskStatus::Pending if self.is_blocked(id, tasks) => TaskStatus::Waiting,
+            status => status,
+        }
+    }
+
+    /// Graphviz of the DAG with tasks colored by their status
+    pub fn graphviz(
+        &self,
+        tasks: &HashMap<Uuid, Task<ResourceProfile>>,
+        schedule: &HashMap<Uuid, Uuid>,
+    ) -> String {
+        let mut ids: Vec<&Uuid> = self.dependencies.keys().collect();
+        ids.sort();
+        let mut dot = format!("digraph \"{}\" {{\n", self.name);
+        for id in &ids {
+            let name = tasks.get(id).map_or_else(|| id.to_string(), |x| x.name().clone());
+            let status = self.status(id, tasks, schedule);
+            dot += &format!(
+                "    \"{}\" [label=\"{} ({:?})\" color=\"{}\"]\n",
+                id,
+                name,
+                status,
+                status.color()
+            );
+        }
+        for id in &ids {
+            for dependency in &self.dependencies[id] {
+                dot += &format!("    \"{}\" -> \"{}\"\n", dependency, id);
+            }
+        }
+        dot + "}\n"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dag(dependencies: &[(&str, &[&str])], failure_policy: FailurePolicy) -> Dag {
+        let task = |name: &str| Task::new(name.to_string(), None, "busybox".to_string(), false, None);
+        Dag {
+            name: "build".to_string(),
+            tasks: vec![task("a"), task("b"), task("c"), task("d")],
+            dependencies: dependencies
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.iter().map(|x| x.to_string()).collect()))
+                .collect(),
+            failure_policy,
+            volume: Some("artifacts".to_string()),
+        }
+    }
+
+    #[test]
+    fn reject_cycle() {
+        assert!(dag(&[("b", &["a"]), ("c", &["b"])], FailurePolicy::FailFast).validate().is_ok());
+        assert!(dag(&[("b", &["a", "c"]), ("c", &["b"])], FailurePolicy::FailFast).validate().is_err());
+        assert!(dag(&[("b", &["e"])], FailurePolicy::FailFast).validate().is_err());
+    }
+
+    #[test]
+    fn failure_policy() {
+        // a -> b -> c, d is independent
+        for (policy, expected) in
+            vec![(FailurePolicy::FailFast, vec!["c", "d"]), (FailurePolicy::Continue, vec!["c"])]
+        {
+            let dag = dag(&[("b", &["a"]), ("c", &["b"])], policy);
+            let ids: HashMap<String, Uuid> =
+                dag.tasks.iter().map(|x| (x.name().clone(), *x.id())).collect();
+            let mut tasks: HashMap<Uuid, Task<ResourceProfile>> =
+                dag.tasks.iter().map(|x| (*x.id(), x.clone())).collect();
+            let workflow = Workflow::new(&dag, &ids);
+            assert!(workflow.is_blocked(&ids["b"], &tasks));
+
+            tasks.get_mut(&ids["a"]).unwrap().set_schedulable(false).set_exit_code(Some(0));
+            assert!(!workflow.is_blocked(&ids["b"], &tasks));
+            assert!(workflow.to_cancel(&tasks).is_empty());
+
+            tasks.get_mut(&ids["b"]).unwrap().set_schedulable(false).set_exit_code(Some(1));
+            let mut expected: Vec<Uuid> = expected.iter().map(|x| ids[*x]).collect();
+            expected.sort();
+            assert_eq!(workflow.to_cancel(&tasks), expected);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use super::ResourceProfile;
+use super::Task;
+use crate::prelude::*;
+use getset::{CopyGetters, Getters, Setters};
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum WorkloadKind {
+    /// Runs `replicas` task instances to completion
+    Job,
+    /// Keeps `replicas` task instances running, finished instances are replaced
+    Service,
+}
+
+impl FromStr for WorkloadKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "job" => Ok(Self::Job),
+            "service" => Ok(Self::Service),
+            _ => Err(format!("unknown workload kind '{}'", s)),
+        }
+    }
+}
+
+/// Job or service expanded by scheduler into `replicas` task instances
+#[derive(Clone, Debug, Serialize, Deserialize, Getters, CopyGetters, Setters)]
+pub struct Workload {
+    #[getset(get = "pub")]
+    id: Uuid,
+    #[getset(get = "pub")]
+    name: String,
+    #[getset(get_copy = "pub", set = "pub")]
+    kind: WorkloadKind,
+    #[getset(get = "pub")]
+    image: String,
+    #[getset(get = "pub")]
+    cmd: Option<String>,
+    #[getset(get_copy = "pub")]
+    realtime: bool,
+    #[getset(get = "pub")]
+    request: Option<ResourceProfile>,
+    /// Namespace of the task instances
+    #[getset(get = "pub", set = "pub")]
+    namespace: String,
+    #[getset(get_copy = "pub", set = "pub")]
+    replicas: usize,
+    /// Number of replicas that can be unavailable during rolling update
+    #[getset(get_copy = "pub", set = "pub")]
+    max_unavailable: usize,
+    /// Increased every time the task template changes
+    #[getset(get_copy = "pub")]
+    revision: u64,
+    /// All replicas have to be placed together
+    #[getset(get_copy = "pub", set = "pub")]
+    gang: bool,
+    next_instance: u64,
+}
+
+/// Changes needed to bring workload task instances to the desired state
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ReconcilePlan {
+    /// Active task instances to be stopped
+    pub retire: Vec<Uuid>,
+    /// Number of task instances of current revision to be created
+    pub create: usize,
+}
+
+impl Workload {
+    pub fn new(
+        name: String,
+        kind: WorkloadKind,
+        image: String,
+        cmd: Option<String>,
+        realtime: bool,
+        request: Option<ResourceProfile>,
+        replicas: usize,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            kind,
+            image,
+            cmd,
+            realtime,
+            request,
+            namespace: super::namespace::DEFAULT.to_string(),
+            replicas,
+            max_unavailable: 1,
+            revision: 0,
+            gang: false,
+            next_instance: 0,
+        }
+    }
+
+    /// Replaces task template by the one of `template`, running instances are replaced by
+    /// rolling update if anything changed
+    pub fn update_template(&mut self, template: &Workload) {
+        if self.image != template.image
+            || self.cmd != template.cmd
+            || self.request != template.request
+            || self.realtime != template.realtime
+            || self.namespace != template.namespace
+        {
+            self.image = template.image.clone();
+            self.cmd = template.cmd.clone();
+            self.request = template.request.clone();
+            self.realtime = template.realtime;
+            self.namespace = template.namespace.clone();
+            self.revision += 1;
+        }
+    }
+
+    /// Creates new task instance from the current template
+    pub fn instantiate(&mut self) -> Task<ResourceProfile> {
+        let mut task = Task::new(
+            format!("{}-{}", self.name, self.next_instance),
+            self.request.clone(),
+            self.image.clone(),
+            self.realtime,
+            self.cmd.clone(),
+        );
+        task.set_owner(Some(self.id)).set_revision(self.revision).set_instance(self.next_instance);
+        task.set_namespace(self.namespace.clone());
+        if self.gang {
+            task.set_gang(Some(format!("{}-{}", self.name, self.revision)));
+        }
+        self.next_instance += 1;
+        task
+    }
+
+    /// Computes which instances to stop and how many to start.
+    ///
+    /// `tasks` are all instances owned by this workload, `placed` are ids of tasks
+    /// currently assigned to a server
+    pub fn plan(&self, tasks: &[&Task<ResourceProfile>], placed: &HashSet<Uuid>) -> ReconcilePlan {
+        let mut plan = ReconcilePlan::default();
+        let (mut active, finished): (Vec<_>, Vec<_>) =
+            tasks.iter().partition(|x| *x.schedulable());
+        // Instances created last are retired first
+        active.sort_by_key(|x| *x.instance());
+
+        let (current, outdated): (Vec<_>, Vec<_>) =
+            active.into_iter().partition(|x| *x.revision() == self.revision);
+
+        // 1. Scale down, outdated instances go first
+        let mut keep_outdated = outdated.len();
+        let mut keep_current = current.len();
+        while keep_outdated + keep_current > self.replicas {
+            if keep_outdated > 0 {
+                keep_outdated -= 1;
+                plan.retire.push(*outdated[keep_outdated].id());
+            } else {
+                keep_current -= 1;
+                plan.retire.push(*current[keep_current].id());
+            }
+        }
+
+        // 2. Rolling update, replace outdated instances once new ones are placed.
+        // Gang is replaced at once as partial gang can't run
+        let pending = current[..keep_current].iter().filter(|x| !placed.contains(x.id())).count();
+        let budget = if self.gang {
+            keep_outdated
+        } else {
+            self.max_unavailable.max(1).saturating_sub(pending)
+        };
+        for task in outdated[..keep_outdated].iter().take(budget) {
+            keep_outdated -= 1;
+            plan.retire.push(*task.id());
+        }
+
+        // 3. Scale up
+        let target = match self.kind {
+            WorkloadKind::Service => self.replicas,
+            WorkloadKind::Job => self.replicas.saturating_sub(finished.len()),
+        };
+        plan.create = target.saturating_sub(keep_outdated + keep_current);
+        plan
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn service(replicas: usize) -> Workload {
+        Workload::new(
+            "web".to_string(),
+            WorkloadKind::Service,
+            "nginx".to_string(),
+            None,
+            false,
+            None,
+            replicas,
+        )
+    }
+
+    #[test]
+    fn scale_up_and_down() {
+        let mut workload = service(3);
+        assert_eq!(workload.plan(&[], &HashSet::new()).create, 3);
+
+        let tasks: Vec<_> = (0..3).map(|_| workload.instantiate()).collect();
+        workload.set_replicas(1);
+        let plan = workload.plan(&tasks.iter().collect::<Vec<_>>(), &HashSet::new());
+        assert_eq!(plan.create, 0);
+        assert_eq!(plan.retire, vec![*tasks[2].id(), *tasks[1].id()]);
+    }
+
+    #[test]
+    fn newest_instance_retired_first() {
+        let mut workload = service(11);
+        let tasks: Vec<_> = (0..11).map(|_| workload.instantiate()).collect();
+        workload.set_replicas(10);
+        let plan = workload.plan(&tasks.iter().collect::<Vec<_>>(), &HashSet::new());
+        assert_eq!(tasks[10].name(), "web-10");
+        assert_eq!(plan.retire, vec![*tasks[10].id()]);
+    }
+
+    #[test]
+    fn rolling_update_waits_for_new_instances() {
+        let mut workload = service(2);
+        let old: Vec<_> = (0..2).map(|_| workload.instantiate()).collect();
+        let mut template = service(2);
+        template.image = "nginx:2".to_string();
+        workload.update_template(&template);
+
+        let tasks: Vec<_> = old.iter().collect();
+        let plan = workload.plan(&tasks, &HashSet::new());
+        assert_eq!(plan.retire.len(), 1);
+        assert_eq!(plan.create, 1);
+
+        let mut old = old;
+        old[0].set_schedulable(false);
+        let new = workload.instantiate();
+        let tasks: Vec<_> = old.iter().chain(std::iter::once(&new)).collect();
+        let plan = workload.plan(&tasks, &HashSet::new());
+        assert_eq!(plan, ReconcilePlan::default());
+
+        let placed = std::iter::once(*new.id()).collect();
+        let plan = workload.plan(&tasks, &placed);
+        assert_eq!(plan.retire, vec![*old[1].id()]);
+        assert_eq!(plan.create, 1);
+    }
+}
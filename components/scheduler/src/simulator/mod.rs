@@ -0,0 +1,369 @@
+//! Discrete-event simulation of a cluster driven by a recorded trace. Scheduler core runs
+//! unchanged on a virtual clock, agents are replaced by a mock starting and stopping tasks in
+//! virtual time.
+
+use crate::prelude::*;
+use crate::scheduler::{
+    ManualClock, ResourceProfile, Resources, Scheduler, Server, State, Task, TaskCommand,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use futures::channel::mpsc;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+#[derive(Deserialize)]
+pub struct Trace {
+    pub servers: Vec<TraceServer>,
+    pub tasks: Vec<TraceTask>,
+}
+
+#[derive(Deserialize)]
+pub struct TraceServer {
+    pub name: String,
+    /// Benchmark profile
+    pub profile: ResourceProfile,
+    pub capacity: Option<Resources>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct TraceTask {
+    pub name: String,
+    pub image: String,
+    pub cmd: Option<String>,
+    /// Seconds since start of the trace
+    pub arrival: u64,
+    /// Seconds of running time needed to finish
+    pub duration: u64,
+    pub request: Option<ResourceProfile>,
+    pub requests: Option<Resources>,
+    /// Profile time series, one sample per profile interval, repeated if task runs longer
+    #[serde(default)]
+    pub profiles: Vec<ResourceProfile>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// Time averaged share of benchmarked cluster resources used by running tasks
+    pub utilization: BTreeMap<String, Decimal>,
+    /// Seconds between arrival and the first start of a task
+    pub mean_queueing_delay: Decimal,
+    pub max_queueing_delay: u64,
+    pub migrations: usize,
+    /// Seconds between the first arrival and the last finished task
+    pub makespan: u64,
+    pub finished: usize,
+    /// Tasks refused by admission control
+    pub rejected: usize,
+    /// Tasks not finished when simulation ended
+    pub unfinished: usize,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    /// Trace task arrives
+    Arrival(usize),
+    /// Task finishes unless it was stopped since, identified by generation
+    Finish(Uuid, u64),
+    /// Agents submit profiles of running tasks
+    Profile,
+}
+
+struct Running {
+    server: Uuid,
+    since: u64,
+}
+
+struct TaskState {
+    trace: usize,
+    /// Running time left
+    remaining: u64,
+    running: Option<Running>,
+    last_server: Option<Uuid>,
+    /// Incremented on every start and stop, invalidates scheduled finish
+    generation: u64,
+    first_start: Option<u64>,
+    finished: bool,
+    /// Index of the current profile sample
+    sample: usize,
+}
+
+pub struct Simulator {
+    scheduler: Scheduler,
+    /// Virtual time read by the scheduler
+    clock: Arc<ManualClock>,
+    /// Virtual time at the start of the trace
+    start: DateTime<Utc>,
+    trace: Trace,
+    /// Seconds between profile submissions
+    interval: u64,
+    /// Simulation stops at this time even if tasks didn't finish
+    horizon: u64,
+    agents: Vec<(Uuid, mpsc::Receiver<TaskCommand>)>,
+    tasks: HashMap<Uuid, TaskState>,
+    events: BinaryHeap<Reverse<(u64, u64, Event)>>,
+    seq: u64,
+    now: u64,
+    /// Resource usage integrated over time
+    usage: BTreeMap<String, Decimal>,
+    report: Report,
+}
+
+impl Simulator {
+    /// Scheduler state is persisted to `data_dir`, it should be empty
+    pub fn new(trace: Trace, data_dir: &Path, interval: u64, horizon: u64) -> Self {
+        let mut scheduler = Scheduler::new(data_dir);
+        let start = Utc.timestamp(0, 0);
+        let clock = Arc::new(ManualClock::new(start));
+        scheduler.set_clock(clock.clone());
+        Self {
+            scheduler,
+            clock,
+            start,
+            trace,
+            interval: interval.max(1),
+            horizon,
+            agents: vec![],
+            tasks: HashMap::new(),
+            events: BinaryHeap::new(),
+            seq: 0,
+            now: 0,
+            usage: BTreeMap::new(),
+            report: Report::default(),
+        }
+    }
+
+    pub async fn run(mut self) -> Report {
+        // 1. Register virtual servers with mock agents
+        let buffer = 2 * self.trace.tasks.len() + 1;
+        for i in 0..self.trace.servers.len() {
+            let id = Uuid::new_v4();
+            let (tx, rx) = mpsc::channel(buffer);
+            self.scheduler.subscribe_server(id, tx);
+            self.agents.push((id, rx));
+            let trace = &self.trace.servers[i];
+            let mut server = Server::new(id, trace.name.clone(), Some(trace.profile.clone()));
+            server.set_labels(trace.labels.clone()).set_capacity(trace.capacity);
+            self.scheduler.insert_server(server).await;
+            self.drain_agents();
+        }
+
+        // 2. Replay trace
+        for i in 0..self.trace.tasks.len() {
+            self.push(self.trace.tasks[i].arrival, Event::Arrival(i));
+        }
+        self.push(self.interval, Event::Profile);
+        let first_arrival = self.trace.tasks.iter().map(|x| x.arrival).min().unwrap_or_default();
+        while let Some(Reverse((time, _, event))) = self.events.pop() {
+            if time > self.horizon {
+                break;
+            }
+            self.integrate_usage(time);
+            self.now = time;
+            let elapsed = chrono::Duration::seconds(time.try_into().unwrap_or(i64::MAX));
+            self.clock.set(self.start + elapsed);
+            match event {
+                Event::Arrival(i) => self.arrive(i).await,
+                Event::Finish(id, generation) => self.finish(id, generation).await,
+                Event::Profile => self.submit_profiles().await,
+            }
+            self.drain_agents();
+        }
+        let last_finish = self.report.makespan;
+        self.report.makespan = last_finish.saturating_sub(first_arrival);
+        self.summarize()
+    }
+
+    fn push(&mut self, time: u64, event: Event) {
+        self.seq += 1;
+        self.events.push(Reverse((time, self.seq, event)));
+    }
+
+    async fn arrive(&mut self, i: usize) {
+        let trace = &self.trace.tasks[i];
+        let mut task = Task::new(
+            trace.name.clone(),
+            trace.request.clone(),
+            trace.image.clone(),
+            false,
+            trace.cmd.clone(),
+        );
+        task.set_requests(trace.requests);
+        let state = TaskState {
+            trace: i,
+            remaining: trace.duration,
+            running: None,
+            last_server: None,
+            generation: 0,
+            first_start: None,
+            finished: false,
+            sample: 0,
+        };
+        let id = *task.id();
+        self.tasks.insert(id, state);
+        if let Err(e) = self.scheduler.insert_task(task).await {
+            debug!("Task '{}' rejected: {}", self.trace.tasks[i].name, e);
+            self.tasks.remove(&id);
+            self.report.rejected += 1;
+        }
+    }
+
+    async fn finish(&mut self, id: Uuid, generation: u64) {
+        let state = match self.tasks.get_mut(&id) {
+            Some(state) if state.generation == generation && state.running.is_some() => state,
+            _ => return,
+        };
+        state.running = None;
+        state.remaining = 0;
+        state.finished = true;
+        self.report.finished += 1;
+        self.report.makespan = self.now;
+        self.scheduler.finish_task(&id, 0).await;
+    }
+
+    /// Every running task submits its current sample, followed by a single scheduling round
+    async fn submit_profiles(&mut self) {
+        let mut samples = vec![];
+        for (id, state) in &mut self.tasks {
+            if let Some(running) = &state.running {
+                let profiles = &self.trace.tasks[state.trace].profiles;
+                if !profiles.is_empty() {
+                    samples.push((*id, running.server, profiles[state.sample % profiles.len()].clone()));
+                }
+                state.sample += 1;
+            }
+        }
+        for (id, server, profile) in samples {
+            if let Err(e) = self.scheduler.insert_task_profile(&id, server, profile) {
+                error!("Can't store simulated profile: {}", e);
+            }
+        }
+        self.scheduler.schedule().await;
+        if self.tasks.values().any(|x| !x.finished) {
+            self.push(self.now + self.interval, Event::Profile);
+        }
+    }
+
+    fn drain_agents(&mut self) {
+        let mut commands = vec![];
+        for (server, rx) in &mut self.agents {
+            while let Ok(Some(cmd)) = rx.try_next() {
+                commands.push((*server, cmd));
+            }
+        }
+        for (server, cmd) in commands {
+            self.execute(server, &cmd);
+            // Mock agent stops containers immediately, also of tasks that already finished
+            if cmd.state == State::Remove {
+                self.scheduler.confirm_removal(&server, cmd.task.id());
+            }
+        }
+    }
+
+    /// Mock agent starting and stopping tasks
+    fn execute(&mut self, server: Uuid, cmd: &TaskCommand) {
+        let now = self.now;
+        let state = match self.tasks.get_mut(cmd.task.id()) {
+            Some(state) if !state.finished => state,
+            _ => return,
+        };
+        match cmd.state {
+            State::Run => {
+                if let Some(running) = state.running.take() {
+                    state.remaining = state.remaining.saturating_sub(now - running.since);
+                }
+                let migrated = state.last_server.map_or(false, |x| x != server);
+                state.last_server = Some(server);
+                state.first_start.get_or_insert(now);
+                state.running = Some(Running { server, since: now });
+                state.generation += 1;
+                let (finish, generation) = (now + state.remaining, state.generation);
+                if migrated {
+                    self.report.migrations += 1;
+                }
+                self.push(finish, Event::Finish(*cmd.task.id(), generation));
+            }
+            State::Remove => {
+                if let Some(running) = state.running.take() {
+                    if running.server == server {
+                        state.remaining = state.remaining.saturating_sub(now - running.since);
+                        state.generation += 1;
+                    } else {
+                        // Already started on the new server
+                        state.running = Some(running);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds usage of running tasks since the previous event
+    fn integrate_usage(&mut self, time: u64) {
+        let elapsed = Decimal::from(time - self.now);
+        for state in self.tasks.values().filter(|x| x.running.is_some()) {
+            let profiles = &self.trace.tasks[state.trace].profiles;
+            if profiles.is_empty() {
+                continue;
+            }
+            for (dimension, value) in profiles[state.sample % profiles.len()].dimensions() {
+                *self.usage.entry(dimension.to_string()).or_default() += value * elapsed;
+            }
+        }
+    }
+
+    fn summarize(mut self) -> Report {
+        let total = self
+            .trace
+            .servers
+            .iter()
+            .fold(ResourceProfile::default(), |acc, x| acc + x.profile.clone());
+        let elapsed = Decimal::from(self.now);
+        for (dimension, used) in &self.usage {
+            let available = total.get(dimension) * elapsed;
+            if !available.is_zero() {
+                self.report.utilization.insert(dimension.clone(), used / available);
+            }
+        }
+        let delays: Vec<u64> = self
+            .tasks
+            .values()
+            .filter_map(|x| Some(x.first_start? - self.trace.tasks[x.trace].arrival))
+            .collect();
+        if !delays.is_empty() {
+            let sum: u64 = delays.iter().sum();
+            self.report.mean_queueing_delay = Decimal::from(sum) / Decimal::from(delays.len() as u64);
+            self.report.max_queueing_delay = delays.iter().copied().max().unwrap_or_default();
+        }
+        self.report.unfinished = self.tasks.values().filter(|x| !x.finished).count();
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn replays_trace() {
+        let trace: Trace = serde_json::from_value(serde_json::json!({
+            "servers": [{ "name": "a", "profile": { "ipc": "10", "memory": "100" } }],
+            "tasks": [
+                { "name": "t1", "image": "x", "arrival": 0, "duration": 60,
+                  "profiles": [{ "ipc": "2", "memory": "10" }] },
+                { "name": "t2", "image": "y", "arrival": 10, "duration": 60,
+                  "profiles": [{ "ipc": "2", "memory": "10" }] },
+            ]
+        }))
+        .unwrap();
+        let data_dir = std::env::temp_dir().join(format!("simulator-test-{}", Uuid::new_v4()));
+        let report = Simulator::new(trace, &data_dir, 30, 3600).run().await;
+        let _ = std::fs::remove_dir_all(&data_dir);
+
+        assert_eq!(report.finished, 2);
+        assert_eq!(report.unfinished, 0);
+        assert_eq!(report.makespan, 70);
+        assert_eq!(report.max_queueing_delay, 0);
+        assert_eq!(report.migrations, 0);
+    }
+}
@@ -1,3 +1,4 @@
+use super::NotLeader;
 use super::Scheduler;
 use super::SchedulerSubscription;
 use crate::prelude::*;
@@ -5,6 +6,7 @@ use crate::scheduler;
 use futures::{FutureExt, StreamExt};
 use handlebars::Handlebars;
 use lazy_static::lazy_static;
+use telemetry::TraceSpan;
 use tokio::sync::mpsc;
 use warp::ws::{Message, WebSocket};
 
@@ -33,17 +35,31 @@ pub async fn graphflow(ws: WebSocket, mut graph_rx: SchedulerSubscription) {
         }
     }));
 
-    let mut flows = graph_rx.recv().await.unwrap();
-
-    loop {
-        if let Err(_disconnected) = tx.send(Ok(Message::text(flows))) {
+    while let Some(round) = graph_rx.recv().await {
+        let round = match round {
+            Some(round) => serde_json::json!(round),
+            None => continue,
+        };
+        if let Err(_disconnected) = tx.send(Ok(Message::text(round.to_string()))) {
             break;
         }
-        flows = graph_rx.recv().await.unwrap();
         log::debug!("New event detected");
     }
 }
 
+/// Writes sent to a passive replica are answered with 503 and the address of the leader
+pub async fn recover_not_leader(
+    rejection: warp::Rejection,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    match rejection.find::<NotLeader>() {
+        Some(NotLeader(leader)) => Ok(warp::reply::with_status(
+            format!("Replica is not leader, leader is '{}'", leader.clone().unwrap_or_default()),
+            warp::http::StatusCode::SERVICE_UNAVAILABLE,
+        )),
+        None => Err(rejection),
+    }
+}
+
 pub async fn get_index() -> Result<impl warp::Reply, warp::reject::Rejection> {
     let source_template = include_str!("./pages/index.hbs");
     let res = HBS.render_template(&source_template[..], &{}).unwrap();
@@ -55,7 +71,21 @@ pub async fn get_server(scheduler: Scheduler) -> Result<impl warp::Reply, warp::
     let source_template = include_str!("./pages/server.hbs");
     let scheduler = scheduler.lock().await;
     let mut map = HashMap::<&'static str, _>::new();
-    map.insert("servers", scheduler.get_servers());
+    let now = scheduler.now();
+    let servers: Vec<_> = scheduler
+        .get_servers()
+        .into_iter()
+        .map(|x| {
+            serde_json::json!({
+                "server": x,
+                "allocatable": x.allocatable(),
+                "effective": x.effective_profile(),
+                "state": x.effective_state(now),
+                "drained": scheduler.is_drained(x, now),
+            })
+        })
+        .collect();
+    map.insert("servers", servers);
 
     let res = HBS.render_template(&source_template[..], &map).unwrap();
     Ok(warp::reply::html(res))
@@ -67,19 +97,12 @@ pub async fn post_server(
 ) -> Result<impl warp::Reply, warp::reject::Rejection> {
     let mut scheduler = scheduler.lock().await;
     if form.contains_key("simulation") {
-        let profile = scheduler::ResourceProfile {
-            ipc: form["ipc"].parse::<Decimal>().unwrap(),
-            memory: form["memory"].parse::<u64>().unwrap(),
-            disk: form["disk"].parse::<u64>().unwrap(),
-            network: form["network"].parse::<u64>().unwrap(),
-        };
-        scheduler
-            .insert_server(scheduler::Server::new(
-                Uuid::new_v4(),
-                form["name"].clone(),
-                Some(profile),
-            ))
-            .await;
+        let profile = parse_profile(&form);
+        let mut server = scheduler::Server::new(Uuid::new_v4(), form["name"].clone(), Some(profile));
+        server
+            .set_labels(form.get("labels").map(|x| scheduler::parse_labels(x)).unwrap_or_default())
+            .set_capacity(parse_resources(&form, "capacity_"));
+        scheduler.insert_server(server).await;
     } else {
         debug!("Copying agent to server");
         tokio::spawn(async move {
@@ -93,6 +116,85 @@ pub async fn post_server(
     Ok(warp::reply::reply())
 }
 
+pub async fn post_server_labels(
+    id: Uuid,
+    scheduler: Scheduler,
+    form: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let mut scheduler = scheduler.lock().await;
+    if let Some(server) = scheduler.get_server(&id) {
+        server.set_labels(form.get("labels").map(|x| scheduler::parse_labels(x)).unwrap_or_default());
+        scheduler.schedule().await;
+    }
+    Ok(warp::reply::reply())
+}
+
+pub async fn post_server_reserved(
+    id: Uuid,
+    scheduler: Scheduler,
+    form: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let mut scheduler = scheduler.lock().await;
+    if let Some(server) = scheduler.get_server(&id) {
+        server.set_reserved(parse_resources(&form, "reserved_").unwrap_or_default());
+        scheduler.schedule().await;
+    }
+    Ok(warp::reply::reply())
+}
+
+pub async fn post_server_state(
+    id: Uuid,
+    scheduler: Scheduler,
+    form: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    use warp::http::StatusCode;
+
+    let state = match form.get("state").map(|x| x.parse::<scheduler::ServerState>()) {
+        Some(Ok(state)) => state,
+        Some(Err(e)) => return Ok(warp::reply::with_status(e, StatusCode::BAD_REQUEST)),
+        None => {
+            let reply = "Missing field 'state'".to_string();
+            return Ok(warp::reply::with_status(reply, StatusCode::BAD_REQUEST));
+        }
+    };
+    let mut scheduler = scheduler.lock().await;
+    if let Some(server) = scheduler.get_server(&id) {
+        server.set_state(state);
+        scheduler.schedule().await;
+    }
+    Ok(warp::reply::with_status(String::new(), StatusCode::OK))
+}
+
+pub async fn post_server_maintenance(
+    id: Uuid,
+    scheduler: Scheduler,
+    form: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    use warp::http::StatusCode;
+
+    let window = form["start"].parse::<chrono::DateTime<chrono::Utc>>().and_then(|start| {
+        let end = form["end"].parse()?;
+        Ok(scheduler::MaintenanceWindow { start, end })
+    });
+    let window = match window {
+        Ok(window) if window.start < window.end => window,
+        Ok(_) => {
+            let reply = "Maintenance must end after it starts".to_string();
+            return Ok(warp::reply::with_status(reply, StatusCode::BAD_REQUEST));
+        }
+        Err(e) => return Ok(warp::reply::with_status(e.to_string(), StatusCode::BAD_REQUEST)),
+    };
+    let mut scheduler = scheduler.lock().await;
+    if let Some(server) = scheduler.get_server(&id) {
+        let mut maintenance = server.maintenance().clone();
+        maintenance.push(window);
+        maintenance.sort();
+        server.set_maintenance(maintenance);
+        scheduler.schedule().await;
+    }
+    Ok(warp::reply::with_status(String::new(), StatusCode::OK))
+}
+
 async fn scp(
     from_host: Option<&str>,
     from: &Path,
@@ -132,15 +234,31 @@ pub async fn get_task(scheduler: Scheduler) -> Result<impl warp::Reply, warp::re
     let source_template = include_str!("./pages/task.hbs");
     let scheduler = scheduler.lock().await;
     let mut map = HashMap::<&'static str, _>::new();
+    let queue: HashMap<Uuid, scheduler::QueueEntry> =
+        scheduler.get_queue().into_iter().map(|x| (x.task, x)).collect();
+    let format_time = |x: Option<chrono::DateTime<chrono::Utc>>| x.map(|x| x.to_rfc3339()).unwrap_or_default();
     let tasks: Vec<HashMap<_, _>> = scheduler
         .get_tasks()
         .iter()
         .map(|x| {
+            let queued = queue.get(x.id());
             vec![
                 ("name", x.name().clone()),
+                ("deadline", format_time(*x.deadline())),
+                ("enqueued_at", format_time(queued.map(|x| x.enqueued_at))),
+                ("estimated_start", format_time(queued.and_then(|x| x.estimated_start))),
                 ("realtime", format!("{}", x.realtime())),
                 ("image", x.image().clone()),
                 ("schedulable", format!("{}", x.schedulable())),
+                ("gang", x.gang().clone().unwrap_or_default()),
+                ("class", x.class().clone().unwrap_or_default()),
+                ("workload_class", format!("{:?}", x.workload_class())),
+                ("namespace", x.namespace().clone()),
+                ("user", x.user().clone().unwrap_or_default()),
+                ("labels", format!("{:?}", x.labels())),
+                ("constraints", format!("{:#?}", x.constraints())),
+                ("requests", format!("{:?}", x.requests())),
+                ("limits", format!("{:?}", x.limits())),
                 ("request", format!("{:#?}", x.request())),
                 ("profile", format!("{:#?}", x.debug_profile())),
                 ("uuid", format!("{:#?}", x.id()))
@@ -159,26 +277,416 @@ pub async fn post_task(
     scheduler: Scheduler,
     form: HashMap<String, String>,
 ) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    // Root of the task's trace, continued until its container starts
+    let span = TraceSpan::start("post_task", &Default::default());
     let mut scheduler = scheduler.lock().await;
     let request = if form.contains_key("simulation") {
-        let request = scheduler::ResourceProfile {
-            ipc: form["ipc"].parse::<Decimal>().unwrap(),
-            memory: form["memory"].parse::<u64>().unwrap(),
-            disk: form["disk"].parse::<u64>().unwrap(),
-            network: form["network"].parse::<u64>().unwrap(),
-        };
-        Some(request)
+        Some(parse_profile(&form))
     } else {
         None
     };
     let cmd = if form["cmd"].is_empty() { None } else { Some(form["cmd"].clone()) };
-    let task = scheduler::Task::new(
+    let mut task = scheduler::Task::new(
         form["name"].clone(),
         request,
         form["image"].clone(),
         form.contains_key("realtime"),
         cmd,
     );
-    scheduler.insert_task(task).await;
+    if let Some(gang) = form.get("gang").filter(|x| !x.is_empty()) {
+        task.set_gang(Some(gang.clone()));
+    }
+    if let Some(class) = form.get("class").filter(|x| !x.is_empty()) {
+        task.set_class(Some(class.clone()));
+    }
+    if let Some(class) = form.get("workload_class").filter(|x| !x.is_empty()) {
+        match class.parse() {
+            Ok(class) => task.set_workload_class(Some(class)),
+            Err(e) => return Ok(warp::reply::with_status(e, warp::http::StatusCode::BAD_REQUEST)),
+        };
+    }
+    if let Some(namespace) = form.get("namespace").filter(|x| !x.is_empty()) {
+        task.set_namespace(namespace.clone());
+    }
+    if let Some(user) = form.get("user").filter(|x| !x.is_empty()) {
+        task.set_user(Some(user.clone()));
+    }
+    if let Some(deadline) = form.get("deadline").filter(|x| !x.is_empty()) {
+        match deadline.parse::<chrono::DateTime<chrono::Utc>>() {
+            Ok(deadline) => task.set_deadline(Some(deadline)),
+            Err(e) => {
+                return Ok(warp::reply::with_status(e.to_string(), warp::http::StatusCode::BAD_REQUEST))
+            }
+        };
+    }
+    let rule = |kind: scheduler::AffinityKind, key: &str| {
+        form.get(key).map(|x| scheduler::parse_labels(x)).filter(|x| !x.is_empty()).map(
+            |selector| scheduler::AffinityRule { kind, selector },
+        )
+    };
+    let affinity = vec![
+        rule(scheduler::AffinityKind::Affinity, "affinity"),
+        rule(scheduler::AffinityKind::AntiAffinity, "anti_affinity"),
+    ];
+    // Clients predating labels don't send them
+    let labels = |key: &str| form.get(key).map(|x| scheduler::parse_labels(x)).unwrap_or_default();
+    task.set_labels(labels("labels")).set_constraints(scheduler::Constraints {
+        node_selector: labels("node_selector"),
+        affinity: affinity.into_iter().flatten().collect(),
+    });
+    task.set_requests(parse_resources(&form, "request_"))
+        .set_limits(parse_resources(&form, "limit_"));
+    span.set_attribute("task", form["name"].clone());
+    match scheduler.insert_task_traced(task, &span.carrier()).await {
+        Ok(()) => Ok(warp::reply::with_status(String::new(), warp::http::StatusCode::OK)),
+        Err(e) => Ok(warp::reply::with_status(e.to_string(), warp::http::StatusCode::BAD_REQUEST)),
+    }
+}
+
+/// Parses profile from form fields named after resource dimensions, non numeric fields are
+/// skipped
+fn parse_profile(form: &HashMap<String, String>) -> scheduler::ResourceProfile {
+    use scheduler::dimension::*;
+
+    [IPC, MEMORY, DISK, NETWORK, CACHE_PRESSURE]
+        .iter()
+        .filter_map(|x| Some((*x, form.get(*x)?.parse::<Decimal>().ok()?)))
+        .collect()
+}
+
+/// Parses absolute resources from `{prefix}cpu`, `{prefix}memory`, `{prefix}disk` and
+/// `{prefix}network` form fields, `None` if all of them are empty
+fn parse_resources(form: &HashMap<String, String>, prefix: &str) -> Option<scheduler::Resources> {
+    let field = |name: &str| -> Option<u64> {
+        form.get(&format!("{}{}", prefix, name)).and_then(|x| x.parse::<u64>().ok())
+    };
+    if ["cpu", "memory", "disk", "network"].iter().all(|x| field(x).is_none()) {
+        return None;
+    }
+    Some(scheduler::Resources {
+        cpu_millis: field("cpu").unwrap_or_default(),
+        memory: field("memory").unwrap_or_default(),
+        disk: field("disk").unwrap_or_default(),
+        network: field("network").unwrap_or_default(),
+    })
+}
+
+pub async fn get_workload(
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let source_template = include_str!("./pages/workload.hbs");
+    let scheduler = scheduler.lock().await;
+    let mut map = HashMap::<&'static str, _>::new();
+    map.insert("workloads", scheduler.get_workloads());
+
+    let res = HBS.render_template(&source_template[..], &map).unwrap();
+    Ok(warp::reply::html(res))
+}
+
+pub async fn post_workload(
+    scheduler: Scheduler,
+    form: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let bad_request = |e: String| warp::reply::with_status(e, warp::http::StatusCode::BAD_REQUEST);
+    let field = |key: &str| form.get(key).ok_or_else(|| format!("missing field '{}'", key));
+    let parse = |key: &str| -> Result<usize, String> {
+        field(key)?.parse().map_err(|e| format!("invalid '{}': {}", key, e))
+    };
+    let (name, kind, image) = match (field("name"), field("kind"), field("image")) {
+        (Ok(name), Ok(kind), Ok(image)) => (name, kind, image),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return Ok(bad_request(e)),
+    };
+    let kind = match kind.parse::<scheduler::WorkloadKind>() {
+        Ok(kind) => kind,
+        Err(e) => return Ok(bad_request(e)),
+    };
+    let (replicas, max_unavailable) = match (parse("replicas"), parse("max_unavailable")) {
+        (Ok(replicas), Ok(max_unavailable)) => (replicas, max_unavailable),
+        (Err(e), _) | (_, Err(e)) => return Ok(bad_request(e)),
+    };
+    let cmd = form.get("cmd").filter(|x| !x.is_empty()).cloned();
+    let mut workload = scheduler::Workload::new(
+        name.clone(),
+        kind,
+        image.clone(),
+        cmd,
+        form.contains_key("realtime"),
+        None,
+        replicas,
+    );
+    workload.set_max_unavailable(max_unavailable).set_gang(form.contains_key("gang"));
+    if let Some(namespace) = form.get("namespace").filter(|x| !x.is_empty()) {
+        workload.set_namespace(namespace.clone());
+    }
+    scheduler.lock().await.insert_workload(workload).await;
+    Ok(warp::reply::with_status(String::new(), warp::http::StatusCode::OK))
+}
+
+pub async fn delete_workload(
+    id: Uuid,
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    scheduler.lock().await.remove_workload(&id).await;
+    Ok(warp::reply::reply())
+}
+
+pub async fn get_interference(
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(&scheduler.interference()))
+}
+
+pub async fn get_classes(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(&scheduler.classes()))
+}
+
+pub async fn get_weights(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(scheduler.weights()))
+}
+
+pub async fn post_weights(
+    scheduler: Scheduler,
+    weights: scheduler::WeightConfig,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    scheduler.lock().await.set_weights(weights).await;
+    Ok(warp::reply::reply())
+}
+
+pub async fn get_aggregation(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(scheduler.aggregation()))
+}
+
+pub async fn post_aggregation(
+    scheduler: Scheduler,
+    policy: scheduler::AggregationPolicy,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    scheduler.lock().await.set_aggregation(policy);
+    Ok(warp::reply::reply())
+}
+
+pub async fn get_profiling(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(scheduler.profiling()))
+}
+
+pub async fn post_profiling(
+    scheduler: Scheduler,
+    policy: scheduler::ProfilingPolicy,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    scheduler.lock().await.set_profiling(policy).await;
+    Ok(warp::reply::reply())
+}
+
+pub async fn get_namespaces(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(&scheduler.get_namespaces()))
+}
+
+pub async fn post_namespace(
+    scheduler: Scheduler,
+    namespace: scheduler::Namespace,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    scheduler.lock().await.insert_namespace(namespace).await;
+    Ok(warp::reply::reply())
+}
+
+pub async fn get_allocations(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(&scheduler.get_allocations()))
+}
+
+/// Hypothetical changes of what-if scheduling
+#[derive(Deserialize)]
+pub struct PlanRequest {
+    #[serde(default)]
+    tasks: Vec<PlannedTask>,
+    #[serde(default)]
+    drain: Vec<Uuid>,
+    weights: Option<scheduler::WeightConfig>,
+}
+
+#[derive(Deserialize)]
+pub struct PlannedTask {
+    name: String,
+    image: String,
+    cmd: Option<String>,
+    requests: Option<scheduler::Resources>,
+}
+
+pub async fn post_plan(
+    scheduler: Scheduler,
+    request: PlanRequest,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let tasks = request
+        .tasks
+        .into_iter()
+        .map(|x| {
+            let mut task = scheduler::Task::new(x.name, None, x.image, false, x.cmd);
+            task.set_requests(x.requests);
+            task
+        })
+        .collect();
+    let scenario =
+        scheduler::Scenario { tasks, drain: request.drain, weights: request.weights };
+    let plan = scheduler.lock().await.plan(&scenario);
+    Ok(warp::reply::json(&plan))
+}
+
+pub async fn get_workflows(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(&scheduler.get_workflows()))
+}
+
+/// DAG of tasks submitted as a workflow
+#[derive(Deserialize)]
+pub struct WorkflowRequest {
+    name: String,
+    tasks: Vec<WorkflowTask>,
+    #[serde(default)]
+    failure_policy: scheduler::FailurePolicy,
+    volume: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WorkflowTask {
+    name: String,
+    image: String,
+    cmd: Option<String>,
+    requests: Option<scheduler::Resources>,
+    /// Names of tasks that have to succeed first
+    #[serde(default)]
+    dependencies: Vec<String>,
+}
+
+pub async fn post_workflow(
+    scheduler: Scheduler,
+    request: WorkflowRequest,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let mut dag = scheduler::Dag {
+        name: request.name,
+        tasks: vec![],
+        dependencies: HashMap::new(),
+        failure_policy: request.failure_policy,
+        volume: request.volume,
+    };
+    for x in request.tasks {
+        let mut task = scheduler::Task::new(x.name.clone(), None, x.image, false, x.cmd);
+        task.set_requests(x.requests);
+        dag.tasks.push(task);
+        if !x.dependencies.is_empty() {
+            dag.dependencies.insert(x.name, x.dependencies);
+        }
+    }
+    match scheduler.lock().await.insert_task(dag).await {
+        Ok(()) => Ok(warp::reply::with_status(String::new(), warp::http::StatusCode::OK)),
+        Err(e) => Ok(warp::reply::with_status(e.to_string(), warp::http::StatusCode::BAD_REQUEST)),
+    }
+}
+
+pub async fn get_scheduled_tasks(
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(&scheduler.get_scheduled_tasks()))
+}
+
+/// Task started at times given by trigger
+#[derive(Deserialize)]
+pub struct ScheduledTaskRequest {
+    name: String,
+    image: String,
+    cmd: Option<String>,
+    trigger: scheduler::Trigger,
+    #[serde(default)]
+    concurrency: scheduler::ConcurrencyPolicy,
+    requests: Option<scheduler::Resources>,
+    namespace: Option<String>,
+}
+
+pub async fn post_scheduled_task(
+    scheduler: Scheduler,
+    request: ScheduledTaskRequest,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let mut scheduler = scheduler.lock().await;
+    let mut scheduled = scheduler::ScheduledTask::new(
+        request.name,
+        request.trigger,
+        request.concurrency,
+        request.image,
+        request.cmd,
+        scheduler.now(),
+    );
+    scheduled.requests = request.requests;
+    scheduled.namespace = request.namespace;
+    scheduler.insert_scheduled_task(scheduled);
+    Ok(warp::reply::reply())
+}
+
+pub async fn delete_scheduled_task(
+    id: Uuid,
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    scheduler.lock().await.remove_scheduled_task(&id);
+    Ok(warp::reply::reply())
+}
+
+pub async fn get_queue(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(&scheduler.get_queue()))
+}
+
+pub async fn get_audit(
+    query: scheduler::AuditQuery,
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let log = scheduler.lock().await.audit_log();
+    match log.query(&query) {
+        Ok(rounds) => Ok(warp::reply::with_status(warp::reply::json(&rounds), warp::http::StatusCode::OK)),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&e.to_string()),
+            warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        )),
+    }
+}
+
+pub async fn get_metrics(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    let (body, status) = match crate::metrics::gather(&scheduler) {
+        Ok(body) => (String::from_utf8_lossy(&body).into_owned(), warp::http::StatusCode::OK),
+        Err(e) => (e.to_string(), warp::http::StatusCode::INTERNAL_SERVER_ERROR),
+    };
+    let reply = warp::reply::with_header(body, "content-type", prometheus::TEXT_FORMAT);
+    Ok(warp::reply::with_status(reply, status))
+}
+
+pub async fn get_queue_policy(scheduler: Scheduler) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(scheduler.queue_policy()))
+}
+
+pub async fn post_queue_policy(
+    scheduler: Scheduler,
+    policy: scheduler::QueuePolicy,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    scheduler.lock().await.set_queue_policy(policy);
+    Ok(warp::reply::reply())
+}
+
+pub async fn get_migration_policy(
+    scheduler: Scheduler,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    let scheduler = scheduler.lock().await;
+    Ok(warp::reply::json(scheduler.migration_policy()))
+}
+
+pub async fn post_migration_policy(
+    scheduler: Scheduler,
+    policy: scheduler::MigrationPolicy,
+) -> Result<impl warp::Reply, warp::reject::Rejection> {
+    scheduler.lock().await.set_migration_policy(policy);
     Ok(warp::reply::reply())
 }
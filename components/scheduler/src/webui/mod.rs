@@ -1,23 +1,94 @@
 mod handlers;
 
+use crate::config::TlsConfig;
+use crate::ha::Leadership;
 use crate::prelude::*;
 use crate::scheduler;
+use std::net::SocketAddr;
 use tokio::sync::watch::Receiver;
 use warp::Filter;
 
-type SchedulerSubscription = Receiver<String>;
+type SchedulerSubscription = Receiver<Option<scheduler::RoundEvent>>;
 type Scheduler = Arc<Mutex<scheduler::Scheduler>>;
 
-pub async fn serve(scheduler: Scheduler) {
+/// Write refused by a passive replica, holds address of the leader if known
+#[derive(Debug)]
+struct NotLeader(Option<String>);
+
+impl warp::reject::Reject for NotLeader {}
+
+/// Serves web UI and API on `address`, over HTTPS when `tls` is given. Passive replicas serve
+/// reads and plans only, writes are refused with the address of the leader.
+pub async fn serve(
+    scheduler: Scheduler,
+    leadership: Arc<Leadership>,
+    address: SocketAddr,
+    tls: Option<TlsConfig>,
+) {
     let flow_subscription = scheduler.lock().await.subscribe();
     let routes = get_schedule_graph()
         .or(get_index())
         .or(get_api_schedule_graph(flow_subscription))
         .or(get_server(scheduler.clone()))
         .or(post_server(scheduler.clone()))
+        .or(post_server_labels(scheduler.clone()))
+        .or(post_server_reserved(scheduler.clone()))
+        .or(post_server_state(scheduler.clone()))
+        .or(post_server_maintenance(scheduler.clone()))
         .or(get_task(scheduler.clone()))
-        .or(post_task(scheduler.clone()));
-    warp::serve(routes).run(([0, 0, 0, 0], 8080)).await;
+        .or(post_task(scheduler.clone()))
+        .or(get_workload(scheduler.clone()))
+        .or(post_workload(scheduler.clone()))
+        .or(delete_workload(scheduler.clone()))
+        .or(get_interference(scheduler.clone()))
+        .or(get_classes(scheduler.clone()))
+        .or(get_weights(scheduler.clone()))
+        .or(post_weights(scheduler.clone()))
+        .or(get_migration_policy(scheduler.clone()))
+        .or(post_migration_policy(scheduler.clone()))
+        .or(get_aggregation(scheduler.clone()))
+        .or(post_aggregation(scheduler.clone()))
+        .or(get_profiling(scheduler.clone()))
+        .or(post_profiling(scheduler.clone()))
+        .or(get_namespaces(scheduler.clone()))
+        .or(post_namespace(scheduler.clone()))
+        .or(get_allocations(scheduler.clone()))
+        .or(get_workflows(scheduler.clone()))
+        .or(post_workflow(scheduler.clone()))
+        .or(get_scheduled_tasks(scheduler.clone()))
+        .or(post_scheduled_task(scheduler.clone()))
+        .or(delete_scheduled_task(scheduler.clone()))
+        .or(get_queue(scheduler.clone()))
+        .or(get_audit(scheduler.clone()))
+        .or(get_metrics(scheduler.clone()))
+        .or(get_queue_policy(scheduler.clone()))
+        .or(post_queue_policy(scheduler.clone()));
+    // What-if plans don't change state, passive replicas compute them too
+    let routes = post_plan(scheduler.clone())
+        .or(leader(leadership).and(routes))
+        .recover(handlers::recover_not_leader);
+    match tls {
+        Some(tls) => warp::serve(routes).tls().cert_path(tls.cert).key_path(tls.key).run(address).await,
+        None => warp::serve(routes).run(address).await,
+    }
+}
+
+/// Rejects requests other than GET unless this replica is the leader
+fn leader(
+    leadership: Arc<Leadership>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::method()
+        .and_then(move |method: warp::http::Method| {
+            let leadership = leadership.clone();
+            async move {
+                if method == warp::http::Method::GET || leadership.is_leader() {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(NotLeader(leadership.leader_address())))
+                }
+            }
+        })
+        .untuple_one()
 }
 
 fn get_schedule_graph() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone
@@ -59,6 +130,50 @@ fn post_server(
         .and_then(handlers::post_server)
 }
 
+fn post_server_labels(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("schedule" / "server" / Uuid / "labels"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_server_labels)
+}
+
+fn post_server_reserved(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("schedule" / "server" / Uuid / "reserved"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_server_reserved)
+}
+
+fn post_server_state(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("schedule" / "server" / Uuid / "state"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_server_state)
+}
+
+fn post_server_maintenance(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("schedule" / "server" / Uuid / "maintenance"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_server_maintenance)
+}
+
 fn get_task(
     scheduler: Scheduler,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -76,3 +191,281 @@ fn post_task(
         .and(warp::body::json())
         .and_then(handlers::post_task)
 }
+
+fn get_workload(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("schedule" / "workload"))
+        .and(scheduler)
+        .and_then(handlers::get_workload)
+}
+
+fn post_workload(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("schedule" / "workload"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_workload)
+}
+
+fn delete_workload(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::delete()
+        .and(warp::path!("schedule" / "workload" / Uuid))
+        .and(scheduler)
+        .and_then(handlers::delete_workload)
+}
+
+fn get_interference(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "interference"))
+        .and(scheduler)
+        .and_then(handlers::get_interference)
+}
+
+fn get_classes(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "classes"))
+        .and(scheduler)
+        .and_then(handlers::get_classes)
+}
+
+fn get_weights(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "weights"))
+        .and(scheduler)
+        .and_then(handlers::get_weights)
+}
+
+fn post_weights(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "weights"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_weights)
+}
+
+fn get_migration_policy(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "migration"))
+        .and(scheduler)
+        .and_then(handlers::get_migration_policy)
+}
+
+fn post_migration_policy(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "migration"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_migration_policy)
+}
+
+fn get_aggregation(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "aggregation"))
+        .and(scheduler)
+        .and_then(handlers::get_aggregation)
+}
+
+fn post_aggregation(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "aggregation"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_aggregation)
+}
+
+fn get_profiling(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "profiling"))
+        .and(scheduler)
+        .and_then(handlers::get_profiling)
+}
+
+fn post_profiling(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "profiling"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_profiling)
+}
+
+fn get_namespaces(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "namespaces"))
+        .and(scheduler)
+        .and_then(handlers::get_namespaces)
+}
+
+fn post_namespace(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "namespaces"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_namespace)
+}
+
+fn get_allocations(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "allocations"))
+        .and(scheduler)
+        .and_then(handlers::get_allocations)
+}
+
+fn get_workflows(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "workflows"))
+        .and(scheduler)
+        .and_then(handlers::get_workflows)
+}
+
+fn post_workflow(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "workflows"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_workflow)
+}
+
+fn get_scheduled_tasks(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "cron"))
+        .and(scheduler)
+        .and_then(handlers::get_scheduled_tasks)
+}
+
+fn post_scheduled_task(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "cron"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_scheduled_task)
+}
+
+fn delete_scheduled_task(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::delete()
+        .and(warp::path!("api" / "schedule" / "cron" / Uuid))
+        .and(scheduler)
+        .and_then(handlers::delete_scheduled_task)
+}
+
+fn get_queue(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "queue"))
+        .and(scheduler)
+        .and_then(handlers::get_queue)
+}
+
+fn get_audit(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "audit"))
+        .and(warp::query::<scheduler::AuditQuery>())
+        .and(scheduler)
+        .and_then(handlers::get_audit)
+}
+
+fn get_metrics(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get().and(warp::path!("metrics")).and(scheduler).and_then(handlers::get_metrics)
+}
+
+fn get_queue_policy(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::get()
+        .and(warp::path!("api" / "schedule" / "queue" / "policy"))
+        .and(scheduler)
+        .and_then(handlers::get_queue_policy)
+}
+
+fn post_queue_policy(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "queue" / "policy"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_queue_policy)
+}
+
+fn post_plan(
+    scheduler: Scheduler,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let scheduler = warp::any().map(move || scheduler.clone());
+    warp::post()
+        .and(warp::path!("api" / "schedule" / "plan"))
+        .and(scheduler)
+        .and(warp::body::json())
+        .and_then(handlers::post_plan)
+}
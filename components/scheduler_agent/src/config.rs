@@ -0,0 +1,140 @@
+//! Options of the agent. Defaults are overridden by the TOML file, environment variables
+//! `SCHEDULER_AGENT_<OPTION>` and command line flags, in this order.
+
+use crate::prelude::*;
+use clap::{App, Arg};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use telemetry::TraceExporter;
+
+/// Name, flag and help of options accepted on command line and in environment
+const OPTIONS: [(&str, &str, &str); 10] = [
+    ("addresses", "addresses", "Scheduler replicas separated by commas"),
+    ("tls_ca", "tls-ca", "PEM certificate authority the scheduler is verified against"),
+    ("tls_cert", "tls-cert", "PEM client certificate presented to the scheduler"),
+    ("tls_key", "tls-key", "PEM private key of the client certificate"),
+    ("tls_domain", "tls-domain", "Domain name expected in the scheduler certificate"),
+    ("log_level", "log-level", "Log level"),
+    ("labels", "labels", "Server labels in key=value,key2=value2 format"),
+    ("metrics_address", "metrics-address", "Address Prometheus metrics are served on"),
+    ("trace_exporter", "trace-exporter", "Exporter of trace spans: none, stdout or otlp"),
+    ("otlp_endpoint", "otlp-endpoint", "Address of OpenTelemetry collector"),
+];
+
+/// Certificates used to connect to the scheduler over TLS
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub ca: PathBuf,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub domain: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Scheduler replicas asked in order which one is the leader
+    pub addresses: Vec<String>,
+    pub tls: Option<TlsConfig>,
+    pub log_level: String,
+    /// Labels constraining which tasks the server runs
+    pub labels: HashMap<String, String>,
+    /// Address `/metrics` is served on
+    pub metrics_address: SocketAddr,
+    pub trace_exporter: TraceExporter,
+    /// Collector spans are sent to by `otlp` exporter
+    pub otlp_endpoint: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addresses: vec!["http://[::1]:50051".to_string()],
+            tls: None,
+            log_level: "trace".to_string(),
+            labels: Default::default(),
+            metrics_address: "[::]:9101".parse().unwrap(),
+            trace_exporter: TraceExporter::default(),
+            otlp_endpoint: "localhost:55680".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads configuration of the process from file given by `--config` or
+    /// `SCHEDULER_AGENT_CONFIG`, environment and command line
+    pub fn from_args() -> BoxResult<Self> {
+        let matches = Self::app().get_matches();
+        let path = matches
+            .value_of("config")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("SCHEDULER_AGENT_CONFIG").map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => {
+                let content = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Can't read config '{}': {}", path.display(), e))?;
+                toml::from_str(&content)
+                    .map_err(|e| format!("Invalid config '{}': {}", path.display(), e))?
+            }
+            None => Self::default(),
+        };
+        config.apply(|x| std::env::var(format!("SCHEDULER_AGENT_{}", x.to_uppercase())).ok())?;
+        config.apply(|x| matches.value_of(x).map(String::from))?;
+        Ok(config)
+    }
+
+    fn app() -> App<'static, 'static> {
+        let config = Arg::with_name("config").short("c").long("config").takes_value(true);
+        let app = App::new("scheduler_agent").arg(config.help("TOML configuration file"));
+        OPTIONS.iter().fold(app, |app, (name, flag, help)| {
+            app.arg(Arg::with_name(name).long(flag).takes_value(true).help(help))
+        })
+    }
+
+    /// Overrides options with values `lookup` finds by option name
+    fn apply(&mut self, lookup: impl Fn(&str) -> Option<String>) -> BoxResult<()> {
+        if let Some(x) = lookup("addresses") {
+            self.addresses =
+                x.split(',').map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect();
+        }
+        if let Some(x) = lookup("tls_ca") {
+            self.tls.get_or_insert_with(Default::default).ca = PathBuf::from(x);
+        }
+        if let Some(x) = lookup("tls_cert") {
+            self.tls.get_or_insert_with(Default::default).cert = Some(PathBuf::from(x));
+        }
+        if let Some(x) = lookup("tls_key") {
+            self.tls.get_or_insert_with(Default::default).key = Some(PathBuf::from(x));
+        }
+        if let Some(x) = lookup("tls_domain") {
+            self.tls.get_or_insert_with(Default::default).domain = Some(x);
+        }
+        if let Some(x) = lookup("log_level") {
+            self.log_level = x;
+        }
+        if let Some(x) = lookup("labels") {
+            self.labels = x
+                .split(',')
+                .filter_map(|x| {
+                    let mut kv = x.splitn(2, '=');
+                    Some((kv.next()?.trim().to_string(), kv.next()?.trim().to_string()))
+                })
+                .filter(|(k, _)| !k.is_empty())
+                .collect();
+        }
+        if let Some(x) = lookup("metrics_address") {
+            self.metrics_address =
+                x.parse().map_err(|e| format!("Invalid metrics_address: {}", e))?;
+        }
+        if let Some(x) = lookup("trace_exporter") {
+            self.trace_exporter = x.parse()?;
+        }
+        if let Some(x) = lookup("otlp_endpoint") {
+            self.otlp_endpoint = x;
+        }
+        Ok(())
+    }
+}
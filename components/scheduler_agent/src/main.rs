@@ -1,5 +1,7 @@
 #![deny(warnings)]
 
+mod config;
+mod metrics;
 mod task;
 mod scheduler {
     tonic::include_proto!("scheduler");
@@ -17,26 +19,104 @@ mod prelude {
     pub type BoxResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
 }
 
+use crate::config::{Config, TlsConfig};
 use crate::prelude::*;
 use crate::scheduler::scheduler_client::SchedulerClient;
 use fern::colors::ColoredLevelConfig;
 use std::cmp::max;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::delay_for;
 use tonic::codec::Streaming;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
 
 #[tokio::main]
 async fn main() -> BoxResult<()> {
-    setup_logger()?;
-    let client = Arc::new(Mutex::new(SchedulerClient::connect("http://[::1]:50051").await?));
-    let tasks = subscribe_tasks(client.clone()).await?;
-    let registration = register(client.clone());
-
+    let config = Config::from_args()?;
+    setup_logger(&config)?;
+    telemetry::init(config.trace_exporter, &config.otlp_endpoint, "scheduler_agent");
+    tokio::spawn(metrics::serve(config.metrics_address));
+    // Containers keep running across reconnects, their watchers share the client and report to
+    // the current leader
+    let client = Arc::new(Mutex::new(wait_for_leader(&config).await));
     let mut task_runner = task::TaskRunner::new();
+    loop {
+        if let Err(e) = run(&config, client.clone(), &mut task_runner).await {
+            log::error!("Lost connection to scheduler: {}", e);
+        }
+        delay_for(Duration::from_secs(5)).await;
+        let leader = wait_for_leader(&config).await;
+        *client.lock().await = leader;
+    }
+}
+
+/// Runs tasks of the leader replica until the connection to it is lost
+async fn run(config: &Config, client: Client, task_runner: &mut task::TaskRunner) -> BoxResult<()> {
+    let tasks = subscribe_tasks(client.clone()).await?;
+    let registration = register(client.clone(), config.labels.clone());
     let tasks = task_runner.process_tasks(client, tasks);
     futures::try_join!(registration, tasks)?;
     Ok(())
 }
 
-fn setup_logger() -> Result<(), fern::InitError> {
+/// Connects to the leader, retrying until some replica is reachable
+async fn wait_for_leader(config: &Config) -> SchedulerClient<Channel> {
+    loop {
+        match connect_leader(config).await {
+            Ok(client) => return client,
+            Err(e) => log::error!("Can't connect to scheduler: {}", e),
+        }
+        delay_for(Duration::from_secs(5)).await;
+    }
+}
+
+/// Connects to the leader, asking replicas in order which one it is
+async fn connect_leader(config: &Config) -> BoxResult<SchedulerClient<Channel>> {
+    for address in &config.addresses {
+        let mut client = match connect(address.clone(), config.tls.as_ref()).await {
+            Ok(client) => client,
+            Err(e) => {
+                debug!("Scheduler replica '{}' unreachable: {}", address, e);
+                continue;
+            }
+        };
+        let leader = match client.get_leader(scheduler::GetLeaderRequest {}).await {
+            Ok(reply) => reply.into_inner(),
+            Err(e) => {
+                debug!("Scheduler replica '{}' doesn't know leader: {}", address, e);
+                continue;
+            }
+        };
+        if leader.is_leader {
+            debug!("Connected to leader '{}'", address);
+            return Ok(client);
+        }
+        if !leader.address.is_empty() {
+            debug!("Connecting to leader '{}'", leader.address);
+            return connect(leader.address, config.tls.as_ref()).await;
+        }
+    }
+    Err("No scheduler replica is leader".into())
+}
+
+/// Connects to replica at `address`, over TLS when configured
+async fn connect(address: String, tls: Option<&TlsConfig>) -> BoxResult<SchedulerClient<Channel>> {
+    let mut endpoint = Channel::from_shared(address)?;
+    if let Some(tls) = tls {
+        let ca = Certificate::from_pem(std::fs::read(&tls.ca)?);
+        let mut config = ClientTlsConfig::new().ca_certificate(ca);
+        if let (Some(cert), Some(key)) = (&tls.cert, &tls.key) {
+            config = config.identity(Identity::from_pem(std::fs::read(cert)?, std::fs::read(key)?));
+        }
+        if let Some(domain) = &tls.domain {
+            config = config.domain_name(domain.clone());
+        }
+        endpoint = endpoint.tls_config(config);
+    }
+    Ok(SchedulerClient::new(endpoint.connect().await?))
+}
+
+fn setup_logger(config: &Config) -> BoxResult<()> {
     let colors = ColoredLevelConfig::new()
         .debug(fern::colors::Color::Green)
         .trace(fern::colors::Color::Blue);
@@ -51,16 +131,24 @@ fn setup_logger() -> Result<(), fern::InitError> {
                 message
             ))
         })
+        .level(config.log_level.parse()?)
         .chain(std::io::stderr())
         .apply()?;
     Ok(())
 }
 
-async fn register(client: Client) -> BoxResult<()> {
+async fn register(client: Client, labels: HashMap<String, String>) -> BoxResult<()> {
     let mut client = client.lock().await;
     let request = tonic::Request::new(scheduler::RegistrationRequest {
         machine_id: MachineId::get().to_string(),
         hostname: hostname::get()?.into_string().unwrap(),
+        labels,
+        capacity: Some(scheduler::Resources {
+            cpu_millis: u64::from(sys_info::cpu_num()?) * 1000,
+            memory: sys_info::mem_info()?.total * 1024,
+            disk: 0,
+            network: 0,
+        }),
     });
 
     let response = client.register_server(request).await?.into_inner();
@@ -0,0 +1,89 @@
+//! Prometheus metrics of the agent and containers it runs
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge_vec, register_int_counter, register_int_counter_vec, register_int_gauge_vec,
+    Encoder, GaugeVec, IntCounter, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::net::SocketAddr;
+use warp::Filter;
+
+lazy_static! {
+    static ref CONTAINER_IPC: GaugeVec = register_gauge_vec!(
+        "agent_container_ipc",
+        "Instructions per cycle of the container",
+        &["container"]
+    )
+    .unwrap();
+    static ref CONTAINER_MEMORY: IntGaugeVec = register_int_gauge_vec!(
+        "agent_container_memory_bytes",
+        "Memory used by the container",
+        &["container"]
+    )
+    .unwrap();
+    static ref CONTAINER_DISK: IntGaugeVec = register_int_gauge_vec!(
+        "agent_container_disk_bytes",
+        "Bytes read and written by the container in the last profiling interval",
+        &["container"]
+    )
+    .unwrap();
+    static ref CONTAINER_NETWORK: IntGaugeVec = register_int_gauge_vec!(
+        "agent_container_network_bytes",
+        "Bytes sent and received by the container in the last profiling interval",
+        &["container"]
+    )
+    .unwrap();
+    static ref CONTAINER_STARTS: IntCounter =
+        register_int_counter!("agent_container_starts_total", "Containers started").unwrap();
+    static ref CONTAINER_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "agent_container_failures_total",
+        "Containers that failed to start or exited with non-zero code",
+        &["stage"]
+    )
+    .unwrap();
+}
+
+/// Sets gauges of `container` to its latest profile
+pub fn observe_profile(container: &str, profile: &profiler::ApplicationProfile) {
+    let labels = &[container];
+    let ipc =
+        if profile.cycles == 0 { 0.0 } else { profile.instructions as f64 / profile.cycles as f64 };
+    CONTAINER_IPC.with_label_values(labels).set(ipc);
+    CONTAINER_MEMORY.with_label_values(labels).set(profile.memory as i64);
+    CONTAINER_DISK.with_label_values(labels).set((profile.vfs_read + profile.vfs_write) as i64);
+    CONTAINER_NETWORK
+        .with_label_values(labels)
+        .set((profile.tcp_send_bytes + profile.tcp_recv_bytes) as i64);
+}
+
+pub fn container_started() {
+    CONTAINER_STARTS.inc();
+}
+
+pub fn container_start_failed() {
+    CONTAINER_FAILURES.with_label_values(&["start"]).inc();
+}
+
+/// Removes gauges of exited `container`, counts it as failed unless `exit_code` is zero
+pub fn container_exited(container: &str, exit_code: i64) {
+    for gauge in &[&*CONTAINER_MEMORY, &*CONTAINER_DISK, &*CONTAINER_NETWORK] {
+        let _ = gauge.remove_label_values(&[container]);
+    }
+    let _ = CONTAINER_IPC.remove_label_values(&[container]);
+    if exit_code != 0 {
+        CONTAINER_FAILURES.with_label_values(&["exit"]).inc();
+    }
+}
+
+/// Serves metrics in Prometheus text format at `/metrics`
+pub async fn serve(address: SocketAddr) {
+    let metrics = warp::get().and(warp::path!("metrics")).map(|| {
+        let mut buffer = vec![];
+        let encoder = TextEncoder::new();
+        if let Err(e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+            log::error!("Can't encode metrics: {}", e);
+        }
+        warp::reply::with_header(buffer, "content-type", encoder.format_type().to_string())
+    });
+    warp::serve(metrics).run(address).await;
+}
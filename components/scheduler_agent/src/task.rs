@@ -1,58 +1,97 @@
+use crate::metrics;
 use crate::prelude::*;
 use crate::scheduler;
 use crate::scheduler::scheduler_client::SchedulerClient;
 use bollard::container::Config;
+use bollard::container::HostConfig;
 use bollard::container::InspectContainerOptions;
 use bollard::container::RemoveContainerOptions;
+use bollard::container::StopContainerOptions;
 use bollard::Docker;
 use futures_util::stream::TryStreamExt;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
+use telemetry::{Carrier, TraceSpan};
 use tokio::stream::StreamExt;
 use tokio::task::JoinHandle;
 use tokio::time::delay_for;
 use tonic::codec::Streaming;
 use tonic::transport::Channel;
 
-pub struct Task<'a> {
+pub struct Task {
     id: String,
     client: Client,
-    docker: &'a Docker,
+    docker: Docker,
     measure_handle: Option<JoinHandle<BoxResult<()>>>,
+    /// Trace context the scheduler continues when the task finishes
+    trace_context: Carrier,
+    /// Set when the scheduler moved or descheduled the task, its exit isn't reported then
+    removed: Arc<AtomicBool>,
 }
 
-impl<'a> Task<'a> {
-    fn new(id: String, client: Arc<Mutex<SchedulerClient<Channel>>>, docker: &'a Docker) -> Self {
-        Self { id, client, docker, measure_handle: None }
+impl Task {
+    fn new(
+        id: String,
+        client: Arc<Mutex<SchedulerClient<Channel>>>,
+        docker: Docker,
+        trace_context: Carrier,
+    ) -> Self {
+        Self {
+            id,
+            client,
+            docker,
+            measure_handle: None,
+            trace_context,
+            removed: Arc::new(AtomicBool::new(false)),
+        }
     }
 
-    async fn measure(&mut self) -> BoxResult<()> {
+    /// Watches container until it exits, profiles are sent to the scheduler if `profiled`
+    async fn measure(&mut self, profiled: bool) -> BoxResult<()> {
         let id = self.id.clone();
         let client = self.client.clone();
         let docker = self.docker.clone();
+        let trace_context = self.trace_context.clone();
+        let removed = self.removed.clone();
         self.measure_handle = Some(tokio::spawn(async move {
             loop {
+                if removed.load(Ordering::SeqCst) {
+                    break;
+                }
                 let options = InspectContainerOptions { size: false };
                 let container = docker.inspect_container(&id, Some(options)).await?;
                 debug!("Container '{}' state: '{}'", id, container.state.status);
                 if !container.state.running {
                     debug!("Exiting profiling for '{}'", id);
-                    client
-                        .lock()
-                        .await
-                        .finish_task(scheduler::FinishTaskRequest {
-                            machine_id: MachineId::get().to_string(),
-                            task_id: id.clone(),
-                        })
-                        .await?;
+                    // Container stopped by the scheduler didn't finish
+                    if removed.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let mut request = tonic::Request::new(scheduler::FinishTaskRequest {
+                        machine_id: MachineId::get().to_string(),
+                        task_id: id.clone(),
+                        exit_code: container.state.exit_code as i64,
+                    });
+                    telemetry::inject_metadata(&trace_context, request.metadata_mut());
+                    client.lock().await.finish_task(request).await?;
                     docker.remove_container(&id, None::<RemoveContainerOptions>).await?;
+                    metrics::container_exited(&id, container.state.exit_code as i64);
                     break;
                 }
+                // Every container is measured for metrics, the scheduler gets profiles only of
+                // tasks without request
                 let pid = container.state.pid.try_into()?;
                 let (mut sender, receiver) = mpsc::channel(10);
                 let client = client.clone();
                 let id = id.clone();
                 let h = tokio::spawn(async move {
                     let profiles = profiler::run(Some(vec![pid]), None, Some(receiver)).await?;
+                    if let Some(profile) = profiles.last() {
+                        metrics::observe_profile(&id, profile);
+                    }
+                    if !profiled {
+                        return Ok(());
+                    }
                     Self::submit_profile(id, client, profiles).await
                 });
                 delay_for(Duration::from_secs(30)).await;
@@ -65,6 +104,12 @@ impl<'a> Task<'a> {
         Ok(())
     }
 
+    /// Stops the container without reporting it finished, the task runs elsewhere or waits
+    async fn remove(self) {
+        self.removed.store(true, Ordering::SeqCst);
+        remove_container(&self.docker, &self.id).await;
+    }
+
     async fn submit_profile(
         task_id: String,
         client: Arc<Mutex<SchedulerClient<Channel>>>,
@@ -84,16 +129,31 @@ impl<'a> Task<'a> {
     }
 }
 
-pub struct TaskRunner<'a> {
-    tasks: Vec<Task<'a>>,
+/// Stops and removes container of task `id`, also when the agent doesn't watch it
+async fn remove_container(docker: &Docker, id: &str) {
+    if let Err(e) = docker.stop_container(id, Some(StopContainerOptions { t: 10 })).await {
+        debug!("Can't stop container '{}': {}", id, e);
+    }
+    let options = Some(RemoveContainerOptions { force: true, ..Default::default() });
+    // Container of a finished task is already removed
+    if let Err(e) = docker.remove_container(id, options).await {
+        debug!("Can't remove container '{}': {}", id, e);
+    }
+    metrics::container_exited(id, 0);
+}
+
+/// Runs containers of tasks, outlives connections to the scheduler so that containers started
+/// before a reconnect are still watched and removed
+pub struct TaskRunner {
+    tasks: Vec<Task>,
     docker: Docker,
 }
-impl<'a> TaskRunner<'a> {
+impl TaskRunner {
     pub fn new() -> Self {
         Self { tasks: vec![], docker: Docker::connect_with_local_defaults().unwrap() }
     }
     pub async fn process_tasks(
-        &'a mut self,
+        &mut self,
         client: Client,
         mut tasks: Streaming<scheduler::SubscribeTasksReply>,
     ) -> BoxResult<()> {
@@ -101,10 +161,29 @@ impl<'a> TaskRunner<'a> {
         while let Some(x) = tasks.next().await {
             let x = x?;
             debug!("Task received '{:#?}'", &x);
-            if &x.state == &1 {
+            let task = x.task.unwrap();
+            if x.state == scheduler::subscribe_tasks_reply::State::Remove as i32 {
+                match self.tasks.iter().position(|x| x.id == task.id) {
+                    Some(i) => self.tasks.remove(i).remove().await,
+                    None => remove_container(&self.docker, &task.id).await,
+                }
+                // Draining server counts as drained once its removed containers are stopped
+                let request = scheduler::ConfirmRemovalRequest {
+                    machine_id: MachineId::get().to_string(),
+                    task_id: task.id.clone(),
+                };
+                client.lock().await.confirm_removal(request).await?;
                 continue;
             }
-            let task = x.task.unwrap();
+            // Trace of the placement continues in the agent
+            let request = scheduler::AcceptTaskRequest {
+                machine_id: MachineId::get().to_string(),
+                task_id: task.id.clone(),
+            };
+            let reply = client.lock().await.accept_task(request).await?;
+            let context = telemetry::extract_metadata(reply.metadata());
+            let span = TraceSpan::start("process_tasks", &context);
+            span.set_attribute("task", task.id.clone());
             use bollard::container::CreateContainerOptions;
             use bollard::container::StartContainerOptions;
             use bollard::image::CreateImageOptions;
@@ -112,20 +191,48 @@ impl<'a> TaskRunner<'a> {
             let options =
                 Some(CreateImageOptions { from_image: &task.image[..], ..Default::default() });
 
+            let image_span = span.child("docker.create_image");
+            image_span.set_attribute("image", task.image.clone());
             self.docker.create_image(options, None, None).try_collect::<Vec<_>>().await?;
+            drop(image_span);
             let options = Some(CreateContainerOptions { name: task.id.clone() });
 
+            // Tasks of a workflow pass artifacts through the shared volume
+            let binds = Some(vec![format!("{}:/artifacts", task.volume)]).filter(|_| !task.volume.is_empty());
+            let host_config = match (task.limits, binds) {
+                (None, None) => None,
+                (limits, binds) => Some(HostConfig {
+                    memory: limits.as_ref().map(|x| x.memory as _),
+                    nano_cpus: limits.as_ref().map(|x| (x.cpu_millis * 1_000_000) as _),
+                    binds,
+                    ..Default::default()
+                }),
+            };
             let config = Config {
                 image: Some(task.image),
                 cmd: task.cmd.map(|x| x.split_whitespace().map(|x| x.to_string()).collect()),
+                host_config,
                 ..Default::default()
             };
-            self.docker.create_container(options, config).await?;
-            self.docker
-                .start_container(&task.id[..], None::<StartContainerOptions<String>>)
-                .await?;
-            let mut task = Task::new(task.id.clone(), client.clone(), &self.docker);
-            task.measure().await?;
+            let started = async {
+                let _span = span.child("docker.create_container");
+                self.docker.create_container(options, config).await?;
+                let _span = span.child("docker.start_container");
+                let options = None::<StartContainerOptions<String>>;
+                self.docker.start_container(&task.id[..], options).await
+            };
+            if let Err(e) = started.await {
+                metrics::container_start_failed();
+                return Err(e.into());
+            }
+            metrics::container_started();
+            let profiled = task.is_profiled;
+            let trace_context = span.carrier();
+            let docker = self.docker.clone();
+            let mut task = Task::new(task.id.clone(), client.clone(), docker, trace_context);
+            task.measure(profiled).await?;
+            // Earlier run of the same task finished or was removed
+            self.tasks.retain(|x| x.id != task.id);
             self.tasks.push(task);
         }
         Ok(())
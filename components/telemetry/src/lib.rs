@@ -0,0 +1,149 @@
+//! Distributed tracing shared by the scheduler and its agents. Spans are recorded only when an
+//! exporter is configured, trace context travels between processes in gRPC metadata.
+
+#![deny(warnings)]
+
+use opentelemetry::api::{self, HttpTextFormat, KeyValue, Span, TraceContextExt, Tracer};
+use opentelemetry::{global, sdk};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+
+/// W3C trace context headers, empty when task isn't traced
+pub type Carrier = BTreeMap<String, String>;
+
+/// Headers of W3C trace context
+const HEADERS: [&str; 2] = ["traceparent", "tracestate"];
+
+/// Name of the instrumentation recording the spans
+const TRACER: &str = "telemetry";
+
+/// Where finished spans are sent
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceExporter {
+    /// Spans are not recorded
+    None,
+    /// Spans are printed, for local testing
+    Stdout,
+    /// Spans are sent to OpenTelemetry collector
+    Otlp,
+}
+
+impl Default for TraceExporter {
+    fn default() -> Self {
+        TraceExporter::None
+    }
+}
+
+impl FromStr for TraceExporter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(TraceExporter::None),
+            "stdout" => Ok(TraceExporter::Stdout),
+            "otlp" => Ok(TraceExporter::Otlp),
+            _ => Err(format!("unknown trace exporter '{}'", s)),
+        }
+    }
+}
+
+/// Installs `exporter` of spans of `service`, `endpoint` is the address of OTLP collector.
+/// Spans are exported in batches by a background task, it has to be called within tokio runtime.
+pub fn init(exporter: TraceExporter, endpoint: &str, service: &'static str) {
+    let builder = match exporter {
+        TraceExporter::None => return,
+        TraceExporter::Stdout => {
+            let exporter = opentelemetry::exporter::trace::stdout::Builder::default().init();
+            let batch =
+                sdk::BatchSpanProcessor::builder(exporter, tokio::spawn, tokio::time::interval);
+            sdk::Provider::builder().with_batch_exporter(batch.build())
+        }
+        TraceExporter::Otlp => {
+            let config = opentelemetry_otlp::ExporterConfig {
+                endpoint: endpoint.to_string(),
+                ..Default::default()
+            };
+            let exporter = opentelemetry_otlp::Exporter::new(config);
+            let batch =
+                sdk::BatchSpanProcessor::builder(exporter, tokio::spawn, tokio::time::interval);
+            sdk::Provider::builder().with_batch_exporter(batch.build())
+        }
+    };
+    let resource = sdk::Resource::new(vec![KeyValue::new("service.name", service)]);
+    let config = sdk::Config { resource: Arc::new(resource), ..Default::default() };
+    global::set_provider(builder.with_config(config).build());
+}
+
+struct CarrierInjector<'a>(&'a mut Carrier);
+
+impl api::Injector for CarrierInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_string(), value);
+    }
+}
+
+struct CarrierExtractor<'a>(&'a Carrier);
+
+impl api::Extractor for CarrierExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+}
+
+/// Span ended when dropped
+pub struct TraceSpan(api::Context);
+
+impl TraceSpan {
+    /// Starts span `name` continuing trace of `parent`, new trace is started if it's empty
+    pub fn start(name: &'static str, parent: &Carrier) -> Self {
+        let parent = api::TraceContextPropagator::new().extract(&CarrierExtractor(parent));
+        let span = global::tracer(TRACER).start_from_context(name, &parent);
+        Self(parent.with_span(span))
+    }
+
+    /// Starts span `name` nested in this one
+    pub fn child(&self, name: &'static str) -> Self {
+        let span = global::tracer(TRACER).start_from_context(name, &self.0);
+        Self(self.0.with_span(span))
+    }
+
+    pub fn set_attribute(&self, key: &'static str, value: impl Into<api::Value>) {
+        self.0.span().set_attribute(KeyValue::new(key, value));
+    }
+
+    /// Context of the span continued by the next step of the trace
+    pub fn carrier(&self) -> Carrier {
+        let mut carrier = Carrier::new();
+        let mut injector = CarrierInjector(&mut carrier);
+        api::TraceContextPropagator::new().inject_context(&self.0, &mut injector);
+        carrier
+    }
+}
+
+impl Drop for TraceSpan {
+    fn drop(&mut self) {
+        self.0.span().end();
+    }
+}
+
+/// Trace context sent in gRPC metadata
+pub fn extract_metadata(metadata: &MetadataMap) -> Carrier {
+    HEADERS
+        .iter()
+        .filter_map(|x| Some((x.to_string(), metadata.get(*x)?.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Adds trace context to gRPC metadata
+pub fn inject_metadata(carrier: &Carrier, metadata: &mut MetadataMap) {
+    for (key, value) in carrier {
+        let key = MetadataKey::from_bytes(key.as_bytes());
+        if let (Ok(key), Ok(value)) = (key, MetadataValue::from_str(value)) {
+            metadata.insert(key, value);
+        }
+    }
+}